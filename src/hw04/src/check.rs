@@ -0,0 +1,994 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::result;
+
+use crate::env::Env;
+use crate::lexer::tokenize;
+use crate::parser::compile;
+use crate::rpn::{Elt, Error, ErrorKind, Op, Result, Stack};
+use crate::vm::{Code, Instr};
+
+/// How many instructions a check may look at before giving up on following
+/// calls and loops, so that checking stays quick however the words nest.
+const FUEL: usize = 100_000;
+
+/// How many times around a loop a check follows before giving up on the types
+/// settling down.
+const MAX_ROUNDS: usize = 16;
+
+/// How deeply a check follows calls to user-defined words.
+const MAX_CALLS: usize = 64;
+
+// Ty
+// The kinds of element that a value on the stack may be, as a set, so that a
+// value can be known to be, say, some real number without knowing which kind.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ty(u16);
+
+impl Ty {
+    pub const INT: Ty = Ty(1);
+    pub const BIG: Ty = Ty(1 << 1);
+    pub const RATIONAL: Ty = Ty(1 << 2);
+    pub const FLOAT: Ty = Ty(1 << 3);
+    pub const COMPLEX: Ty = Ty(1 << 4);
+    pub const BOOL: Ty = Ty(1 << 5);
+    pub const STR: Ty = Ty(1 << 6);
+    pub const SYM: Ty = Ty(1 << 7);
+    pub const LIST: Ty = Ty(1 << 8);
+    pub const QUOTE: Ty = Ty(1 << 9);
+    /// An integer of any size.
+    pub const INTEGER: Ty = Ty(Ty::INT.0 | Ty::BIG.0);
+    /// Any number but a complex one.
+    pub const REAL: Ty = Ty(Ty::INTEGER.0 | Ty::RATIONAL.0 | Ty::FLOAT.0);
+    pub const NUMBER: Ty = Ty(Ty::REAL.0 | Ty::COMPLEX.0);
+    pub const ANY: Ty = Ty((1 << 10) - 1);
+
+    /// The kind of an element.
+    pub fn of(e: &Elt) -> Ty {
+        return match e {
+            Elt::Int(_) => Ty::INT,
+            Elt::Big(_) => Ty::BIG,
+            Elt::Rational(_) => Ty::RATIONAL,
+            Elt::Float(_) => Ty::FLOAT,
+            Elt::Complex(_) => Ty::COMPLEX,
+            Elt::Bool(_) => Ty::BOOL,
+            Elt::Str(_) => Ty::STR,
+            Elt::Sym(_) => Ty::SYM,
+            Elt::List(_) => Ty::LIST,
+            Elt::Quote(_) => Ty::QUOTE,
+        };
+    }
+
+    /// The kinds that either type allows.
+    pub fn union(self, other: Ty) -> Ty {
+        return Ty(self.0 | other.0);
+    }
+
+    /// The kinds that both types allow.
+    pub fn meet(self, other: Ty) -> Ty {
+        return Ty(self.0 & other.0);
+    }
+
+    /// Whether the type allows no kinds at all, so that no value has it.
+    pub fn is_empty(self) -> bool {
+        return self.0 == 0;
+    }
+
+    /// Whether every kind that `other` allows, this type allows too.
+    pub fn contains(self, other: Ty) -> bool {
+        return other.0 & !self.0 == 0;
+    }
+}
+
+/// The names that types are written with, largest first, so that a type is
+/// written with as few names as possible.
+const NAMES: &[(&str, Ty)] = &[
+    ("any", Ty::ANY), ("num", Ty::NUMBER), ("real", Ty::REAL), ("int", Ty::INT), ("big", Ty::BIG),
+    ("rational", Ty::RATIONAL), ("float", Ty::FLOAT), ("complex", Ty::COMPLEX), ("bool", Ty::BOOL),
+    ("str", Ty::STR), ("sym", Ty::SYM), ("list", Ty::LIST), ("quote", Ty::QUOTE),
+];
+
+// Types print as the names of the kinds they allow, e.g. `str|list`.
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rest = *self;
+        let mut names = vec![];
+        for (name, ty) in NAMES {
+            if rest.contains(*ty) && !rest.meet(*ty).is_empty() {
+                names.push(*name);
+                rest = Ty(rest.0 & !ty.0);
+            }
+        }
+        if names.is_empty() {
+            return write!(f, "none");
+        }
+        return write!(f, "{}", names.join("|"));
+    }
+}
+
+/// The types of the values an operator pops, top first, and of those it
+/// pushes, bottom first. Operators that only rearrange the stack, or that
+/// depend on more than the types of their arguments, are left to `Checker::op`.
+fn signature(op: Op) -> (&'static [Ty], &'static [Ty]) {
+    const N: Ty = Ty::NUMBER;
+    const R: Ty = Ty::REAL;
+    const I: Ty = Ty::INT;
+    const FC: Ty = Ty(Ty::FLOAT.0 | Ty::COMPLEX.0);
+    const EXACT: Ty = Ty(Ty::INTEGER.0 | Ty::RATIONAL.0);
+    return match op {
+        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem | Op::Pow => (&[N, N], &[N]),
+        Op::Abs | Op::Re | Op::Im => (&[N], &[R]),
+        Op::Min | Op::Max => (&[R, R], &[R]),
+        Op::Sqrt | Op::Sin | Op::Cos | Op::Tan | Op::Asin | Op::Acos | Op::Atan | Op::Exp | Op::Ln | Op::Log => {
+            (&[N], &[FC])
+        }
+        Op::Floor | Op::Ceil | Op::Round | Op::Trunc => (&[R], &[R]),
+        Op::Numer | Op::Denom => (&[EXACT], &[Ty::INTEGER]),
+        Op::ToInt => (&[R], &[Ty::INTEGER]),
+        Op::ToFloat => (&[R], &[Ty::FLOAT]),
+        Op::Arg => (&[N], &[Ty::FLOAT]),
+        Op::Cplx | Op::Rect => (&[R, R], &[Ty::COMPLEX]),
+        Op::Conj => (&[N], &[N]),
+        Op::Polar => (&[N], &[Ty::FLOAT, Ty::FLOAT]),
+        Op::Concat => (&[Ty::STR, Ty::STR], &[Ty::STR]),
+        Op::Len => (&[Ty(Ty::STR.0 | Ty::LIST.0)], &[I]),
+        Op::Substr => (&[I, I, Ty::STR], &[Ty::STR]),
+        Op::Upper | Op::Lower => (&[Ty::STR], &[Ty::STR]),
+        Op::ToStr => (&[Ty::ANY], &[Ty::STR]),
+        Op::ToNum => (&[Ty::STR], &[N]),
+        Op::Range => (&[I, I], &[Ty::LIST]),
+        Op::Nth => (&[I, Ty::LIST], &[Ty::ANY]),
+        Op::Append => (&[Ty::ANY, Ty::LIST], &[Ty::LIST]),
+        Op::Eq | Op::Ne => (&[Ty::ANY, Ty::ANY], &[Ty::BOOL]),
+        Op::And | Op::Or | Op::Xor => (&[Ty::BOOL, Ty::BOOL], &[Ty::BOOL]),
+        Op::Not => (&[Ty::BOOL], &[Ty::BOOL]),
+        Op::BAnd | Op::BOr | Op::BXor => (&[Ty::INTEGER, Ty::INTEGER], &[Ty::INTEGER]),
+        Op::BNot => (&[Ty::INTEGER], &[Ty::INTEGER]),
+        Op::Shl | Op::Shr => (&[I, Ty::INTEGER], &[Ty::INTEGER]),
+        Op::Rand => (&[Ty::INTEGER], &[Ty::INTEGER]),
+        Op::Seed => (&[I], &[]),
+        Op::Depth => (&[], &[I]),
+        Op::Lt | Op::Le | Op::Gt | Op::Ge | Op::Neg | Op::Swap | Op::Dup | Op::Drop | Op::Over | Op::Rot | Op::RotBack | Op::Nip | Op::Tuck
+        | Op::Pick | Op::Roll | Op::Clear | Op::Quit => (&[], &[]),
+    };
+}
+
+/// How an operator that only rearranges the stack does so: how many values it
+/// pops, and which of them it pushes back, bottom first, counting from 0 at
+/// the top.
+fn shuffle(op: Op) -> Option<(usize, &'static [usize])> {
+    return match op {
+        Op::Swap => Some((2, &[0, 1])),
+        Op::Dup => Some((1, &[0, 0])),
+        Op::Drop => Some((1, &[])),
+        Op::Over => Some((2, &[1, 0, 1])),
+        Op::Rot => Some((3, &[1, 0, 2])),
+        Op::RotBack => Some((3, &[0, 2, 1])),
+        Op::Nip => Some((2, &[0])),
+        Op::Tuck => Some((2, &[0, 1, 0])),
+        _ => None,
+    };
+}
+
+/// How many values an operator pops, if it is always the same number.
+pub fn arity(op: Op) -> Option<usize> {
+    if let Some((n, _)) = shuffle(op) {
+        return Some(n);
+    }
+    return match op {
+        Op::Lt | Op::Le | Op::Gt | Op::Ge => Some(2),
+        Op::Neg => Some(1),
+        // These pop as many values as the one on top says, or all of them.
+        Op::Pick | Op::Roll | Op::Clear => None,
+        _ => Some(signature(op).0.len()),
+    };
+}
+
+// Effect
+// What running some code does to the stack: the types of the values it takes,
+// and of those it leaves in their place, both from the bottom up. Code that
+// runs something which cannot be followed, such as a quotation, has no known
+// result.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Effect {
+    pub inputs: Vec<Ty>,
+    pub outputs: Option<Vec<Ty>>,
+}
+
+// Effects print in the style of Forth's stack comments, e.g. `( num num -- num )`.
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let outputs = match &self.outputs {
+            Some(outputs) => outputs,
+            None => return write!(f, "( ? )"),
+        };
+        write!(f, "(")?;
+        for ty in &self.inputs {
+            write!(f, " {}", ty)?;
+        }
+        write!(f, " --")?;
+        for ty in outputs {
+            write!(f, " {}", ty)?;
+        }
+        return write!(f, " )");
+    }
+}
+
+// Summary
+// What checking a line found: the effect of the line as a whole, and of each
+// word that it defines, in the order they are defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub effect: Effect,
+    pub words: Vec<(String, Effect)>,
+}
+
+/// A value on the stack while checking: its type, and which of the inputs it
+/// is, if it was taken from below the values the code pushed itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Val {
+    ty: Ty,
+    input: Option<usize>,
+}
+
+/// The stack as far as a check knows it at some point in the code.
+#[derive(Clone, Debug, PartialEq)]
+struct State {
+    /// The values known about, bottom first.
+    stack: Vec<Val>,
+    /// Whether there may be values below `stack`, which nothing is known about.
+    open: bool,
+    /// Whether something ran that could have done anything to the stack, so
+    /// that the effect of the code is no longer known.
+    lost: bool,
+    /// The types of the values taken from below `stack`, in the order taken.
+    inputs: Vec<Ty>,
+    /// How many values have been pushed, less those popped.
+    height: i64,
+    /// The height at the start of each enclosing list literal, innermost last,
+    /// if it can be compared with the current one.
+    marks: Vec<Option<i64>>,
+}
+
+impl State {
+    /// A stack holding exactly the given values.
+    fn known(values: &[Elt]) -> State {
+        let stack = values.iter().map(|e| Val { ty: Ty::of(e), input: None }).collect();
+        return State { stack, open: false, lost: false, inputs: vec![], height: 0, marks: vec![] };
+    }
+
+    /// A stack of any depth, holding anything.
+    fn open() -> State {
+        return State { stack: vec![], open: true, lost: false, inputs: vec![], height: 0, marks: vec![] };
+    }
+
+    fn push(&mut self, ty: Ty) {
+        self.push_val(Val { ty, input: None });
+    }
+
+    fn push_val(&mut self, val: Val) {
+        self.stack.push(val);
+        self.height += 1;
+    }
+
+    /// Pops a value, which fails only if the stack is certain to be empty.
+    fn pop(&mut self) -> Result<Val> {
+        self.height -= 1;
+        if let Some(val) = self.stack.pop() {
+            return Ok(val);
+        } else if !self.open {
+            return Err(ErrorKind::Underflow.into());
+        } else if self.lost {
+            return Ok(Val { ty: Ty::ANY, input: None });
+        }
+        self.inputs.push(Ty::ANY);
+        return Ok(Val { ty: Ty::ANY, input: Some(self.inputs.len() - 1) });
+    }
+
+    /// Requires a value to have a type, which fails only if it certainly does
+    /// not. Otherwise, the value is known to have it from then on, so an input
+    /// used as a number must be given a number.
+    fn narrow(&mut self, val: Val, ty: Ty) -> Result<Ty> {
+        let mut narrowed = val.ty.meet(ty);
+        if let Some(i) = val.input {
+            narrowed = narrowed.meet(self.inputs[i]);
+            self.inputs[i] = narrowed;
+        }
+        if narrowed.is_empty() {
+            return Err(ErrorKind::Type.into());
+        }
+        return Ok(narrowed);
+    }
+
+    /// Pops a value, requiring it to have a type.
+    fn pop_ty(&mut self, ty: Ty) -> Result<Ty> {
+        let val = self.pop()?;
+        return self.narrow(val, ty);
+    }
+
+    /// Forgets everything about the stack, after running something that could
+    /// have done anything to it.
+    fn lose(&mut self) {
+        self.stack.clear();
+        self.open = true;
+        self.lost = true;
+        self.marks.iter_mut().for_each(|mark| *mark = None);
+    }
+
+    /// Combines the states at the ends of two paths through the code, giving
+    /// one that allows for either.
+    fn merge(mut self, other: State) -> State {
+        if self.stack.len() != other.stack.len() || self.inputs.len() != other.inputs.len()
+            || self.open != other.open || self.height != other.height {
+            self.lose();
+            return self;
+        }
+        for (val, theirs) in self.stack.iter_mut().zip(other.stack) {
+            val.ty = val.ty.union(theirs.ty);
+            if val.input != theirs.input {
+                val.input = None;
+            }
+        }
+        for (ty, theirs) in self.inputs.iter_mut().zip(other.inputs) {
+            *ty = ty.union(theirs);
+        }
+        for (mark, theirs) in self.marks.iter_mut().zip(other.marks) {
+            if *mark != theirs {
+                *mark = None;
+            }
+        }
+        self.lost |= other.lost;
+        return self;
+    }
+
+    /// The effect of the code that led to this state, from an open stack.
+    fn effect(&self) -> Effect {
+        return Effect {
+            inputs: self.inputs.iter().rev().copied().collect(),
+            outputs: if self.lost { None } else { Some(self.stack.iter().map(|val| val.ty).collect()) },
+        };
+    }
+}
+
+/// Combines the results of the paths that code may take. If every path fails,
+/// so does the code, with the first error.
+fn join(results: Vec<Result<Option<State>>>) -> Result<Option<State>> {
+    let mut joined: Option<State> = None;
+    let mut failure = None;
+    let mut ends = false;
+    for res in results {
+        match res {
+            Ok(Some(state)) => {
+                joined = Some(match joined {
+                    Some(joined) => joined.merge(state),
+                    None => state,
+                });
+            }
+            Ok(None) => ends = true,
+            Err(e) => {
+                failure.get_or_insert(e);
+            }
+        }
+    }
+    return match (joined, failure) {
+        (None, Some(e)) if !ends => Err(e),
+        (joined, _) => Ok(joined),
+    };
+}
+
+/// Locates an error at the instruction that caused it, as running the code
+/// would.
+fn locate(code: &Code, i: usize, e: Error) -> Error {
+    let (instr, pos) = &code.instrs[i];
+    let token = &code.tokens[*pos];
+    let e = e.during(&instr.word(token));
+    return match code.spans.get(*pos) {
+        Some(span) => e.at(&token.to_string(), span.clone()),
+        None => e,
+    };
+}
+
+// Checker
+// Follows code without running it, keeping track of only how deep the stack
+// is and the types of the values on it, so as to find errors that running the
+// code is certain to hit, such as adding a boolean. Anything that depends on
+// the values themselves, such as which way an `if` goes, is allowed for either
+// way, so only certain errors are reported.
+pub struct Checker<'e> {
+    env: &'e Env,
+    /// The state after the code checked so far, or None if it always quits.
+    state: Option<State>,
+    /// Words defined by the code checked so far, or None for those that may or
+    /// may not have been.
+    defs: HashMap<String, Option<Rc<Code>>>,
+    /// The effect of each word defined, in order.
+    words: Vec<(String, Effect)>,
+    /// Whether variables or words may have changed since the check started.
+    changed: bool,
+    /// The words being followed into, innermost last.
+    calling: Vec<String>,
+    /// How many branches or loops the code being checked is inside.
+    nested: usize,
+    /// Whether the code being checked is the body of a word being defined,
+    /// which only runs later, when words and variables may be different.
+    defining: bool,
+    /// Whether errors in the body of a word being defined are reported, though
+    /// they only happen if the word is called.
+    strict: bool,
+    fuel: usize,
+}
+
+impl Checker<'_> {
+    /// Creates a checker for code run on a stack holding exactly `values`,
+    /// bottom first.
+    pub fn new<'e>(env: &'e Env, values: &[Elt]) -> Checker<'e> {
+        return Checker::with_state(env, State::known(values));
+    }
+
+    /// Creates a checker for code run on a stack that could hold anything, so
+    /// as to find the effect of the code.
+    pub fn open(env: &Env) -> Checker<'_> {
+        return Checker::with_state(env, State::open());
+    }
+
+    fn with_state(env: &Env, state: State) -> Checker<'_> {
+        return Checker {
+            env, state: Some(state), defs: HashMap::new(), words: vec![], changed: false, calling: vec![],
+            nested: 0, defining: false, strict: false, fuel: FUEL,
+        };
+    }
+
+    /// Sets whether errors in the bodies of words being defined are reported,
+    /// rather than left to fail when the words are called.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Checks code, carrying on from where the code checked before it left the
+    /// stack. Fails with the first error that running the code is certain to
+    /// hit, located as running it would.
+    pub fn check(&mut self, code: &Code) -> Result<()> {
+        if let Some(state) = self.state.take() {
+            self.state = self.block(code, 0..code.instrs.len(), state)?;
+        }
+        return Ok(());
+    }
+
+    /// Whether the code checked so far always quits, so that anything after it
+    /// never runs.
+    pub fn quits(&self) -> bool {
+        return self.state.is_none();
+    }
+
+    /// The effect of the code checked so far.
+    pub fn effect(&self) -> Effect {
+        return match &self.state {
+            Some(state) => state.effect(),
+            None => Effect { inputs: vec![], outputs: None },
+        };
+    }
+
+    /// The effect of each word defined by the code checked so far, in the
+    /// order they were defined.
+    pub fn words(&self) -> &[(String, Effect)] {
+        return &self.words;
+    }
+
+    /// Checks a range of instructions, on a branch or in a loop, which may or
+    /// may not run.
+    fn nested(&mut self, code: &Code, range: Range<usize>, state: State) -> Result<Option<State>> {
+        self.nested += 1;
+        let res = self.block(code, range, state);
+        self.nested -= 1;
+        return res;
+    }
+
+    /// Checks a range of instructions, which form whole control structures,
+    /// giving the state after them, or None if they always quit.
+    fn block(&mut self, code: &Code, range: Range<usize>, mut state: State) -> Result<Option<State>> {
+        let mut pc = range.start;
+        while pc < range.end {
+            if self.fuel == 0 {
+                // Whatever is left could do anything.
+                self.changed = true;
+                state.lose();
+                return Ok(Some(state));
+            }
+            self.fuel -= 1;
+            let at = pc;
+            pc += 1;
+
+            match &code.instrs[at].0 {
+                Instr::If(to) => {
+                    state.pop_ty(Ty::BOOL).map_err(|e| locate(code, at, e))?;
+                    let end = match code.instrs[to - 1].0 {
+                        Instr::Jump(end) => end,
+                        _ => *to,
+                    };
+                    let then_branch = self.nested(code, pc..to - 1, state.clone());
+                    let else_branch = self.nested(code, *to..end, state);
+                    state = match join(vec![then_branch, else_branch])? {
+                        Some(state) => state,
+                        None => return Ok(None),
+                    };
+                    pc = end;
+                }
+                Instr::While(to) => {
+                    let start = match code.instrs[to - 1].0 {
+                        Instr::Jump(start) => start,
+                        _ => at,
+                    };
+                    state.pop_ty(Ty::BOOL).map_err(|e| locate(code, at, e))?;
+                    // Another time around is the body followed by the condition.
+                    state = self.repeat(state, |checker, state| {
+                        let state = match checker.nested(code, pc..to - 1, state)? {
+                            Some(state) => state,
+                            None => return Ok(None),
+                        };
+                        let mut state = match checker.nested(code, start..at, state)? {
+                            Some(state) => state,
+                            None => return Ok(None),
+                        };
+                        state.pop_ty(Ty::BOOL).map_err(|e| locate(code, at, e))?;
+                        return Ok(Some(state));
+                    });
+                    pc = *to;
+                }
+                Instr::Do(to) => {
+                    state.pop_ty(Ty::INT).and_then(|_| state.pop_ty(Ty::INT)).map_err(|e| locate(code, at, e))?;
+                    state = self.repeat(state, |checker, state| checker.nested(code, pc..to - 1, state));
+                    pc = *to;
+                }
+                instr => {
+                    state = match self.step(instr, state) {
+                        Ok(Some(state)) => state,
+                        Ok(None) => return Ok(None),
+                        Err(e) => {
+                            // The body of a definition has no spans, so its
+                            // errors are located at the name being defined.
+                            let pos = code.instrs[at].1;
+                            return Err(match (instr, code.spans.get(pos + 1)) {
+                                (Instr::Define(name, _), Some(span)) => e.during(name).at(name, span.clone()),
+                                _ => locate(code, at, e),
+                            });
+                        }
+                    };
+                }
+            }
+        }
+        return Ok(Some(state));
+    }
+
+    /// Follows a loop around, starting from the state in which it may first
+    /// be left, until the states it may be left in stop changing. Loops which
+    /// keep changing the stack are given up on.
+    fn repeat<F>(&mut self, mut exit: State, mut again: F) -> State
+        where F: FnMut(&mut Checker, State) -> Result<Option<State>> {
+        for _ in 0..MAX_ROUNDS {
+            let round = again(self, exit.clone());
+            // A loop may always be left before a round that fails.
+            let joined = join(vec![Ok(Some(exit.clone())), round]).ok().flatten().unwrap_or_else(State::open);
+            if joined == exit {
+                return exit;
+            }
+            exit = joined;
+        }
+        exit.lose();
+        return exit;
+    }
+
+    /// Checks a single instruction, other than a jump, giving the state after
+    /// it, or None if it quits.
+    fn step(&mut self, instr: &Instr, mut state: State) -> Result<Option<State>> {
+        match instr {
+            Instr::Push(e) => state.push(Ty::of(e)),
+            Instr::Op(op) => return self.op(*op, state),
+            Instr::Name(name) => return self.name(name, state),
+            Instr::Fail => return Err(ErrorKind::Syntax.into()),
+            Instr::Store => {
+                state.pop_ty(Ty::SYM)?;
+                state.pop()?;
+                self.changed = true;
+            }
+            Instr::Forget => {
+                state.pop_ty(Ty::SYM)?;
+                self.changed = true;
+                self.defs.values_mut().for_each(|def| *def = None);
+            }
+            Instr::Words | Instr::Jump(_) | Instr::Loop(_) => {}
+            Instr::Index(_) => state.push(Ty::INT),
+            Instr::Call => {
+                state.pop_ty(Ty::QUOTE)?;
+                self.give_up(&mut state);
+            }
+            Instr::Map | Instr::Filter | Instr::Each | Instr::Fold => {
+                state.pop_ty(Ty::QUOTE)?;
+                if let Instr::Fold = instr {
+                    state.pop()?;
+                }
+                state.pop_ty(Ty::LIST)?;
+                self.give_up(&mut state);
+                if let Instr::Map | Instr::Filter = instr {
+                    state.push(Ty::LIST);
+                }
+            }
+            Instr::Define(name, body) => self.define(name, body)?,
+            Instr::Quote(_) => state.push(Ty::QUOTE),
+            Instr::ListStart => state.marks.push(Some(state.height)),
+            Instr::ListEnd => {
+                // The items may not take anything from outside the braces.
+                match state.marks.pop().flatten() {
+                    Some(mark) if state.height < mark => return Err(ErrorKind::Underflow.into()),
+                    Some(mark) => {
+                        for _ in mark..state.height {
+                            state.pop()?;
+                        }
+                    }
+                    None => state.lose(),
+                }
+                state.push(Ty::LIST);
+            }
+            Instr::If(_) | Instr::While(_) | Instr::Do(_) => unreachable!("control structures are checked whole"),
+        }
+        return Ok(Some(state));
+    }
+
+    /// Gives up on following what some code does, after running a quotation,
+    /// which could do anything to the stack, variables or words.
+    fn give_up(&mut self, state: &mut State) {
+        self.changed = true;
+        self.defs.values_mut().for_each(|def| *def = None);
+        state.lose();
+    }
+
+    /// Checks a built-in operator.
+    fn op(&mut self, op: Op, mut state: State) -> Result<Option<State>> {
+        if let Some((n, order)) = shuffle(op) {
+            let mut vals = vec![];
+            for _ in 0..n {
+                vals.push(state.pop()?);
+            }
+            for i in order {
+                state.push_val(vals[*i]);
+            }
+            return Ok(Some(state));
+        }
+
+        match op {
+            // Arithmetic pops both of its arguments before looking at either.
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem | Op::Pow => {
+                let x = state.pop()?;
+                let y = state.pop()?;
+                state.narrow(y, Ty::NUMBER)?;
+                state.narrow(x, Ty::NUMBER)?;
+                state.push(Ty::NUMBER);
+            }
+            // So does comparison, which needs two numbers or two strings.
+            Op::Lt | Op::Le | Op::Gt | Op::Ge => {
+                const ORDERED: Ty = Ty(Ty::REAL.0 | Ty::STR.0);
+                let x = state.pop()?;
+                let y = state.pop()?;
+                let y = state.narrow(y, ORDERED)?;
+                let x = state.narrow(x, ORDERED)?;
+                let both = |ty: Ty| !x.meet(ty).is_empty() && !y.meet(ty).is_empty();
+                if !both(Ty::REAL) && !both(Ty::STR) {
+                    return Err(ErrorKind::Type.into());
+                }
+                state.push(Ty::BOOL);
+            }
+            Op::Neg => {
+                let x = state.pop_ty(Ty(Ty::NUMBER.0 | Ty::BOOL.0))?;
+                state.push(if Ty::BOOL.contains(x) { Ty::BOOL } else if Ty::NUMBER.contains(x) { Ty::NUMBER } else { x });
+            }
+            Op::Pick | Op::Roll => {
+                state.pop_ty(Ty::INT)?;
+                if state.stack.is_empty() && !state.open {
+                    return Err(ErrorKind::Underflow.into());
+                }
+                if let Op::Roll = op {
+                    state.lose();
+                } else if state.open {
+                    state.push(Ty::ANY);
+                } else {
+                    let ty = state.stack.iter().fold(Ty(0), |ty, val| ty.union(val.ty));
+                    state.push(ty);
+                }
+            }
+            Op::Clear => {
+                state.lose();
+                state.open = false;
+            }
+            Op::Quit => return Ok(None),
+            _ => {
+                let (inputs, outputs) = signature(op);
+                for ty in inputs {
+                    state.pop_ty(*ty)?;
+                }
+                for ty in outputs {
+                    state.push(*ty);
+                }
+            }
+        }
+        return Ok(Some(state));
+    }
+
+    /// Checks a name, which calls a word if there is one by that name, or
+    /// pushes a variable.
+    fn name(&mut self, name: &str, mut state: State) -> Result<Option<State>> {
+        // Words and variables may be different by the time a word being
+        // defined runs, so its names could do anything.
+        if self.defining {
+            self.give_up(&mut state);
+            return Ok(Some(state));
+        }
+        let body = match self.defs.get(name) {
+            Some(def) => def.clone(),
+            None if self.changed => None,
+            None => match self.env.word(name) {
+                Some(body) => Some(body),
+                None => {
+                    state.push(Ty::of(&self.env.recall(name)?));
+                    return Ok(Some(state));
+                }
+            },
+        };
+        let body = match body {
+            Some(body) if !self.calling.iter().any(|calling| calling == name) && self.calling.len() < MAX_CALLS => body,
+            // Recursion is not followed, as it may go any number of times.
+            _ => {
+                self.give_up(&mut state);
+                return Ok(Some(state));
+            }
+        };
+        self.calling.push(name.to_string());
+        let res = self.block(&body, 0..body.instrs.len(), state);
+        self.calling.pop();
+        return res;
+    }
+
+    /// Checks a word definition, and the body of the word as it would run if
+    /// called straight away, on a stack that could hold anything.
+    fn define(&mut self, name: &str, body: &Rc<Code>) -> Result<()> {
+        // A word defined on only some paths may or may not exist afterwards.
+        let def = if self.nested == 0 { Some(body.clone()) } else { None };
+        self.defs.insert(name.to_string(), def);
+
+        let saved = (self.defs.clone(), self.changed, self.defining);
+        self.defining = true;
+        self.calling.push(name.to_string());
+        let res = self.block(body, 0..body.instrs.len(), State::open());
+        self.calling.pop();
+        (self.defs, self.changed, self.defining) = saved;
+
+        let effect = match res {
+            Ok(Some(state)) => state.effect(),
+            Err(e) if self.strict => return Err(e),
+            Ok(None) | Err(_) => Effect { inputs: vec![], outputs: None },
+        };
+        self.words.push((name.to_string(), effect));
+        return Ok(());
+    }
+}
+
+/// Checks a line of RPN before it runs on `stack`, failing if running it, or
+/// any word it defines, is certain to fail, and otherwise working out its
+/// effect, and that of any words it defines.
+pub fn check_line(stack: &Stack, env: &Env, line: &str) -> Result<Summary> {
+    let (tokens, spans) = tokenize(line)?.into_iter().unzip();
+    let code = compile(tokens, spans)?;
+    let mut checker = Checker::new(env, stack.values());
+    checker.set_strict(true);
+    checker.check(&code)?;
+    let mut checker = Checker::open(env);
+    checker.set_strict(true);
+    checker.check(&code)?;
+    return Ok(Summary { effect: checker.effect(), words: checker.words().to_vec() });
+}
+
+/// Checks a script before it runs on `stack`, failing with the first error
+/// that running it is certain to hit, along with the number of its line. Lines
+/// that do not compile, and words that would fail if called, are left to fail
+/// when they are reached.
+pub fn check_script(stack: &Stack, env: &Env, script: &str) -> result::Result<(), (usize, Error)> {
+    let mut checker = Checker::new(env, stack.values());
+    for (i, line) in script.lines().enumerate() {
+        if i == 0 && line.starts_with("#!") {
+            continue;
+        }
+        let code = match tokenize(line).and_then(|tokens| {
+            let (tokens, spans) = tokens.into_iter().unzip();
+            return compile(tokens, spans);
+        }) {
+            Ok(code) => code,
+            Err(_) => return Ok(()),
+        };
+        checker.check(&code).map_err(|e| (i + 1, e))?;
+        if checker.quits() {
+            return Ok(());
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks a line on an empty stack, with nothing defined.
+    fn check(line: &str) -> Result<Summary> {
+        return check_line(&Stack::new(), &Env::new(), line);
+    }
+
+    /// The effect of a line on a stack that could hold anything, as it prints.
+    fn effect(line: &str) -> String {
+        let (tokens, spans) = tokenize(line).unwrap().into_iter().unzip();
+        let env = Env::new();
+        let mut checker = Checker::open(&env);
+        checker.check(&compile(tokens, spans).unwrap()).unwrap();
+        return checker.effect().to_string();
+    }
+
+    #[test]
+    fn test_ty() {
+        assert_eq!(Ty::of(&Elt::Int(1)).to_string(), "int");
+        assert_eq!(Ty::REAL.to_string(), "real");
+        assert_eq!(Ty::INTEGER.to_string(), "int|big");
+        assert_eq!(Ty::STR.union(Ty::LIST).union(Ty::NUMBER).to_string(), "num|str|list");
+        assert_eq!(Ty::ANY.to_string(), "any");
+        assert!(Ty::NUMBER.meet(Ty::BOOL).is_empty());
+        assert!(Ty::NUMBER.contains(Ty::FLOAT));
+    }
+
+    #[test]
+    fn test_check_type() {
+        let res = check("true 1 +");
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        assert_eq!(res.unwrap_err().span(), Some(7..8));
+        let res = check("1 \"a\" <-> concat");
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        let res = check("1 2 3 rot if then");
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        // The same value may not be used as two different types.
+        let res = check(": f dup 1 + <-> \"a\" concat ;");
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        assert!(check("1 2 = if 1 else \"a\" then 1 +").is_ok());
+        let res = check("1 \"a\" <");
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        let res = check("1 2 < 3 and");
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        assert!(check("1 2 = if 1 else \"a\" then 2 >").is_ok());
+    }
+
+    #[test]
+    fn test_check_underflow() {
+        let res = check("1 +");
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
+        let res = check("1 2 { + }");
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
+        let res = check("x");
+        if let Err(Error { kind: ErrorKind::Unbound(_), .. }) = res {} else { assert!(false); }
+        // Errors only on some paths, or after quitting, are not certain.
+        assert!(check("1 2 = if + then").is_ok());
+        assert!(check("0 0 do + loop").is_ok());
+        assert!(check("quit +").is_ok());
+        // Nor is anything after running a quotation.
+        assert!(check("[ 1 2 ] call + +").is_ok());
+    }
+
+    #[test]
+    fn test_check_stack() {
+        let mut stack = Stack::new();
+        stack.push(Elt::Str("a".to_string())).unwrap();
+        let env = Env::new();
+        let res = check_line(&stack, &env, "1 +");
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        assert!(check_line(&stack, &env, "upper").is_ok());
+        let res = check_line(&stack, &env, "clear drop");
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_effect() {
+        assert_eq!(effect("1 2 +"), "( -- num )");
+        assert_eq!(effect("+"), "( num num -- num )");
+        assert_eq!(effect("<-> dup"), "( any any -- any any any )");
+        assert_eq!(effect("len 1 { 2 } \"a\""), "( str|list -- int int list str )");
+        assert_eq!(effect("if 1 else 1.5 then"), "( bool -- int|float )");
+        assert_eq!(effect("begin dup 0 = while 1 - repeat"), "( any -- any )");
+        assert_eq!(effect("0 10 0 do i + loop"), "( -- num )");
+        assert_eq!(effect("call"), "( ? )");
+        assert_eq!(effect("if drop then"), "( ? )");
+
+        let summary = check(": sq dup * ; : cube dup sq * ; 2 cube").unwrap();
+        assert_eq!(summary.effect.to_string(), "( -- num )");
+        let words: Vec<String> = summary.words.iter().map(|(name, effect)| format!("{} {}", name, effect)).collect();
+        // Words called by a word may be redefined before it runs.
+        assert_eq!(words, ["sq ( num -- num )", "cube ( ? )"]);
+        assert_eq!(check(": fact dup 1 = if else dup 1 - fact * then ;").unwrap().words[0].1.to_string(), "( ? )");
+    }
+
+    #[test]
+    fn test_arity() {
+        assert_eq!(arity(Op::Add), Some(2));
+        assert_eq!(arity(Op::Sqrt), Some(1));
+        assert_eq!(arity(Op::Substr), Some(3));
+        assert_eq!(arity(Op::Rot), Some(3));
+        assert_eq!(arity(Op::Ge), Some(2));
+        assert_eq!(arity(Op::Depth), Some(0));
+        assert_eq!(arity(Op::Pick), None);
+    }
+
+    #[test]
+    fn test_signatures() {
+        // Every operator, on every combination of up to three kinds of value,
+        // fails the check only where it fails when run, in the same way, and
+        // otherwise leaves values of the types the check gives.
+        let samples: Vec<Elt> = ["2", "99999999999", "1/2", "0.5", "1+1i"].iter()
+            .map(|s| Elt::parse_number(s).unwrap())
+            .chain(vec![
+                Elt::Bool(true), Elt::Str("2".to_string()), Elt::Sym("x".to_string()), Elt::List(vec![Elt::Int(1)]),
+                Elt::Quote(Rc::new(Code::default())),
+            ])
+            .collect();
+        let mut stacks: Vec<Vec<Elt>> = vec![vec![]];
+        for depth in 0..3 {
+            let mut deeper = vec![];
+            for values in stacks.iter().filter(|values| values.len() == depth) {
+                for sample in &samples {
+                    let mut values = values.clone();
+                    values.push(sample.clone());
+                    deeper.push(values);
+                }
+            }
+            stacks.extend(deeper);
+        }
+
+        let env = Env::new();
+        for op in Op::all() {
+            let (tokens, spans) = tokenize(op.word()).unwrap().into_iter().unzip();
+            let code = compile(tokens, spans).unwrap();
+            for values in &stacks {
+                let mut checker = Checker::new(&env, values);
+                let checked = checker.check(&code);
+                let mut stack = Stack::new();
+                values.iter().for_each(|e| stack.push(e.clone()).unwrap());
+                let ran = stack.eval(op);
+                let case = format!("`{}` on {:?}", op.word(), values);
+                match (checked, ran) {
+                    (Err(e), Err(ran)) => assert_eq!(e.kind.to_string(), ran.kind.to_string(), "{}", case),
+                    (Err(e), Ok(())) => panic!("{} fails the check with {} but runs", case, e),
+                    (Ok(()), Ok(())) => {
+                        if let Some(outputs) = checker.effect().outputs {
+                            let kinds: Vec<Ty> = stack.values().iter().map(Ty::of).collect();
+                            assert_eq!(outputs.len(), kinds.len(), "{}", case);
+                            for (ty, kind) in outputs.iter().zip(kinds) {
+                                assert!(ty.contains(kind), "{} leaves {} where the check gives {}", case, kind, ty);
+                            }
+                        }
+                    }
+                    (Ok(()), Err(_)) => {}
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_script() {
+        let stack = Stack::new();
+        let env = Env::new();
+        assert!(check_script(&stack, &env, "#!/usr/bin/env hw04\n: sq dup * ;\n3 sq\n'x store\nx sq").is_ok());
+        let res = check_script(&stack, &env, "1\n2\n\"a\" +\n+");
+        if let Err((3, Error { kind: ErrorKind::Type, .. })) = res {} else { assert!(false); }
+        let res = check_script(&stack, &env, "1\n2 +\n+");
+        if let Err((3, Error { kind: ErrorKind::Underflow, .. })) = res {} else { assert!(false); }
+        // A line that does not compile stops the check.
+        assert!(check_script(&stack, &env, "1\n: ;\n+ +").is_ok());
+        // Words are checked as they are when called, not as they are defined.
+        assert!(check_script(&stack, &env, ": g \"a\" ;\n: f g 1 + ;\n: g 1 ;\nf").is_ok());
+        assert!(check_script(&stack, &env, ": f true 1 + ;\n1").is_ok());
+        let res = check_script(&stack, &env, ": f true 1 + ;\nf");
+        if let Err((2, Error { kind: ErrorKind::Type, .. })) = res {} else { assert!(false); }
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use crate::lexer::Token;
+use crate::rpn::{Elt, ErrorKind, Result};
+use crate::vm::Code;
+
+// Env
+// The evaluator's memory, mapping variable names to the values stored in them,
+// and word names to the code they were compiled to. Like the stack, it keeps
+// what it held at the last checkpoint, as only the names changed since, so
+// that a line which fails can be undone without copying everything.
+#[derive(Default)]
+pub struct Env {
+    vars: HashMap<String, Elt>,
+    words: BTreeMap<String, Rc<Code>>,
+    /// What each variable changed since the last checkpoint was bound to
+    /// before, if anything.
+    saved_vars: HashMap<String, Option<Elt>>,
+    /// The same for words.
+    saved_words: HashMap<String, Option<Rc<Code>>>,
+}
+
+impl Env {
+    /// Creates a new, empty environment.
+    pub fn new() -> Env {
+        return Env::default();
+    }
+
+    /// Binds a value to a name, replacing any previous value. Fails if a word
+    /// has the name, since names run the word if there is one.
+    pub fn store(&mut self, name: &str, val: Elt) -> Result<()> {
+        if self.words.contains_key(name) {
+            return Err(ErrorKind::NameInUse(name.to_string()).into());
+        }
+        let old = self.vars.insert(name.to_string(), val);
+        if !self.saved_vars.contains_key(name) {
+            self.saved_vars.insert(name.to_string(), old);
+        }
+        return Ok(());
+    }
+
+    /// Looks up the value bound to a name.
+    pub fn recall(&self, name: &str) -> Result<Elt> {
+        return match self.vars.get(name) {
+            Some(e) => Ok(e.clone()),
+            None => Err(ErrorKind::Unbound(name.to_string()).into()),
+        };
+    }
+
+    /// Lists all variables and their values, in no particular order.
+    pub fn vars(&self) -> impl Iterator<Item = (&String, &Elt)> {
+        return self.vars.iter();
+    }
+
+    /// Defines a word, replacing any previous definition with the same name.
+    /// Fails if a variable has the name, which the word would hide.
+    pub fn define(&mut self, name: &str, code: Rc<Code>) -> Result<()> {
+        if self.vars.contains_key(name) {
+            return Err(ErrorKind::NameInUse(name.to_string()).into());
+        }
+        let old = self.words.insert(name.to_string(), code);
+        self.save_word(name, old);
+        return Ok(());
+    }
+
+    /// Looks up the compiled body of a word. The body is shared, so that a
+    /// word may keep running even if it redefines or forgets itself.
+    pub fn word(&self, name: &str) -> Option<Rc<Code>> {
+        return self.words.get(name).cloned();
+    }
+
+    /// Removes the definition of a word.
+    pub fn forget(&mut self, name: &str) -> Result<()> {
+        return match self.words.remove(name) {
+            Some(old) => {
+                self.save_word(name, Some(old));
+                Ok(())
+            }
+            None => Err(ErrorKind::Unbound(name.to_string()).into()),
+        };
+    }
+
+    /// Keeps what a word was before it first changed since the last checkpoint.
+    fn save_word(&mut self, name: &str, old: Option<Rc<Code>>) {
+        if !self.saved_words.contains_key(name) {
+            self.saved_words.insert(name.to_string(), old);
+        }
+    }
+
+    /// Lists all defined words and their bodies, sorted by name.
+    pub fn words(&self) -> impl Iterator<Item = (&String, &Vec<Token>)> {
+        return self.words.iter().map(|(name, code)| (name, &code.tokens));
+    }
+
+    /// Keeps the variables and words as they are now, to go back to if a
+    /// later change is rolled back.
+    pub fn checkpoint(&mut self) {
+        self.saved_vars.clear();
+        self.saved_words.clear();
+    }
+
+    /// Puts back the variables and words changed since the last checkpoint.
+    pub fn rollback(&mut self) {
+        for (name, old) in self.saved_vars.drain() {
+            match old {
+                Some(val) => self.vars.insert(name, val),
+                None => self.vars.remove(&name),
+            };
+        }
+        for (name, old) in self.saved_words.drain() {
+            match old {
+                Some(code) => self.words.insert(name, code),
+                None => self.words.remove(&name),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::compile;
+    use crate::rpn::Error;
+
+    fn code(body: &[Token]) -> Rc<Code> {
+        return Rc::new(compile(body.to_vec(), vec![]).unwrap());
+    }
+
+    #[test]
+    fn test_store_recall() {
+        let mut env = Env::new();
+        env.store("x", Elt::Int(42)).unwrap();
+        assert_eq!(env.recall("x").unwrap(), Elt::Int(42));
+
+        env.store("x", Elt::Bool(true)).unwrap();
+        assert_eq!(env.recall("x").unwrap(), Elt::Bool(true));
+    }
+
+    #[test]
+    fn test_recall_unbound() {
+        let env = Env::new();
+        let res = env.recall("x");
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Unbound(name), .. }) = res { assert_eq!(name, "x"); } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_define_forget() {
+        let mut env = Env::new();
+        let body = vec![Token::Word("2".to_string()), Token::Word("+".to_string())];
+        env.define("double", code(&body)).unwrap();
+        assert_eq!(env.word("double").unwrap().tokens, body);
+        assert!(env.forget("double").is_ok());
+        assert!(env.word("double").is_none());
+
+        let res = env.forget("double");
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Unbound(name), .. }) = res { assert_eq!(name, "double"); } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_checkpoint_rollback() {
+        let mut env = Env::new();
+        env.store("x", Elt::Int(1)).unwrap();
+        env.define("f", code(&[])).unwrap();
+        env.checkpoint();
+        env.store("x", Elt::Int(2)).unwrap();
+        env.store("x", Elt::Int(3)).unwrap();
+        env.store("y", Elt::Int(4)).unwrap();
+        env.define("g", code(&[])).unwrap();
+        assert!(env.forget("f").is_ok());
+        env.rollback();
+        assert_eq!(env.recall("x").unwrap(), Elt::Int(1));
+        assert!(env.recall("y").is_err());
+        assert!(env.word("f").is_some());
+        assert!(env.word("g").is_none());
+
+        // Rolling back again changes nothing.
+        env.rollback();
+        assert_eq!(env.recall("x").unwrap(), Elt::Int(1));
+    }
+
+    #[test]
+    fn test_name_in_use() {
+        let mut env = Env::new();
+        env.define("f", code(&[])).unwrap();
+        env.store("x", Elt::Int(1)).unwrap();
+        let res = env.store("f", Elt::Int(42));
+        if let Err(Error { kind: ErrorKind::NameInUse(name), .. }) = res { assert_eq!(name, "f"); } else { assert!(false); }
+        let res = env.define("x", code(&[]));
+        if let Err(Error { kind: ErrorKind::NameInUse(name), .. }) = res { assert_eq!(name, "x"); } else { assert!(false); }
+
+        // Once the word is forgotten, its name is free.
+        env.forget("f").unwrap();
+        assert!(env.store("f", Elt::Int(42)).is_ok());
+    }
+
+    #[test]
+    fn test_words_sorted() {
+        let mut env = Env::new();
+        env.define("b", code(&[])).unwrap();
+        env.define("a", code(&[Token::Word("1".to_string())])).unwrap();
+        let names: Vec<&String> = env.words().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,362 @@
+use std::ops::Range;
+
+use crate::check::arity;
+use crate::lexer::Token;
+use crate::rpn::{Elt, Error, ErrorKind, Op, Result};
+
+/// How tightly unary minus binds: tighter than `*`, but looser than `^`, so
+/// that `-2 ^ 2` is -4.
+const NEG_PRECEDENCE: u8 = 7;
+
+/// How tightly `not` binds: looser than comparisons, so that `not x == y` is
+/// `not (x == y)`, but tighter than `and`.
+const NOT_PRECEDENCE: u8 = 3;
+
+/// A piece of an infix expression.
+#[derive(Debug, PartialEq)]
+enum Lexeme {
+    Number(String),
+    Name(String),
+    /// An operator or punctuation, such as `+`, `==` or `(`.
+    Symbol(&'static str),
+}
+
+/// Looks up a binary operator, giving the operator it compiles to, how tightly
+/// it binds, and whether it groups to the right.
+fn binary(symbol: &str) -> Option<(Op, u8, bool)> {
+    return match symbol {
+        "or" => Some((Op::Or, 1, false)),
+        "xor" => Some((Op::Xor, 1, false)),
+        "and" => Some((Op::And, 2, false)),
+        "==" => Some((Op::Eq, 4, false)),
+        "!=" => Some((Op::Ne, 4, false)),
+        "<" => Some((Op::Lt, 4, false)),
+        "<=" => Some((Op::Le, 4, false)),
+        ">" => Some((Op::Gt, 4, false)),
+        ">=" => Some((Op::Ge, 4, false)),
+        "+" => Some((Op::Add, 5, false)),
+        "-" => Some((Op::Sub, 5, false)),
+        "*" => Some((Op::Mul, 6, false)),
+        "/" => Some((Op::Div, 6, false)),
+        "%" => Some((Op::Rem, 6, false)),
+        "^" => Some((Op::Pow, 8, true)),
+        _ => None,
+    };
+}
+
+/// Splits an infix expression into lexemes, each with the span of columns it
+/// covers.
+fn lex(line: &str) -> Result<Vec<(Lexeme, Range<usize>)>> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut lexemes = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        // Some symbols take two characters.
+        let pair: String = chars[i..].iter().take(2).collect();
+        let lexeme = if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '0' && matches!(chars.get(i + 1), Some('x' | 'X' | 'b' | 'B' | 'o' | 'O')) {
+            // An integer in another base, such as `0x1F`.
+            i += 2;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            Lexeme::Number(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit() || c == '.' {
+            // Digits and points, an optional exponent, and an optional `i`
+            // for imaginary numbers.
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && matches!(chars[i], 'e' | 'E') {
+                i += 1;
+                if i < chars.len() && matches!(chars[i], '+' | '-') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            if i < chars.len() && chars[i] == 'i' {
+                i += 1;
+            }
+            Lexeme::Number(chars[start..i].iter().collect())
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            // The boolean operators are words, but work like symbols.
+            match name.as_str() {
+                "and" => Lexeme::Symbol("and"),
+                "or" => Lexeme::Symbol("or"),
+                "xor" => Lexeme::Symbol("xor"),
+                "not" => Lexeme::Symbol("not"),
+                _ => Lexeme::Name(name),
+            }
+        } else if let Some(symbol) = ["==", "!=", "<=", ">="].iter().copied().find(|s| *s == pair) {
+            i += 2;
+            Lexeme::Symbol(symbol)
+        } else {
+            i += 1;
+            match c {
+                '+' => Lexeme::Symbol("+"),
+                '-' => Lexeme::Symbol("-"),
+                '*' => Lexeme::Symbol("*"),
+                '/' => Lexeme::Symbol("/"),
+                '%' => Lexeme::Symbol("%"),
+                '^' => Lexeme::Symbol("^"),
+                '<' => Lexeme::Symbol("<"),
+                '>' => Lexeme::Symbol(">"),
+                '(' => Lexeme::Symbol("("),
+                ')' => Lexeme::Symbol(")"),
+                ',' => Lexeme::Symbol(","),
+                _ => return Err(Error::from(ErrorKind::Syntax).at(&c.to_string(), start..i)),
+            }
+        };
+        lexemes.push((lexeme, start..i));
+    }
+    return Ok(lexemes);
+}
+
+/// Compiles an infix expression, such as `-x ^ 2 + max(1, y) * 3`, into the RPN
+/// tokens that compute it (here `x 2 pow ~ 1 y max 3 * +`), each with the span
+/// of the part of the expression it came from. Operators compile to the words
+/// of their `Op`s, names followed by arguments in parentheses compile to a call
+/// of that word, and other names to a variable.
+pub fn compile(line: &str) -> Result<Vec<(Token, Range<usize>)>> {
+    let mut compiler = Compiler { lexemes: lex(line)?, pos: 0, out: vec![] };
+    if compiler.lexemes.is_empty() {
+        return Ok(vec![]);
+    }
+    compiler.expr(0)?;
+    if let Some((lexeme, span)) = compiler.lexemes.get(compiler.pos) {
+        let kind = if *lexeme == Lexeme::Symbol(")") { ErrorKind::Unbalanced } else { ErrorKind::Syntax };
+        return Err(compiler.error(kind, span.clone()));
+    }
+    return Ok(compiler.out);
+}
+
+// Compiler
+// Compiles lexemes by precedence climbing, emitting each operator after its
+// operands.
+struct Compiler {
+    lexemes: Vec<(Lexeme, Range<usize>)>,
+    pos: usize,
+    out: Vec<(Token, Range<usize>)>,
+}
+
+impl Compiler {
+    fn peek(&self) -> Option<&Lexeme> {
+        return self.lexemes.get(self.pos).map(|(lexeme, _)| lexeme);
+    }
+
+    fn emit(&mut self, word: &str, span: Range<usize>) {
+        self.out.push((Token::Word(word.to_string()), span));
+    }
+
+    /// Builds an error located at a span of the expression.
+    fn error(&self, kind: ErrorKind, span: Range<usize>) -> Error {
+        let text = match self.lexemes.iter().find(|(_, s)| *s == span) {
+            Some((Lexeme::Number(s), _)) | Some((Lexeme::Name(s), _)) => s.to_string(),
+            Some((Lexeme::Symbol(s), _)) => s.to_string(),
+            None => String::new(),
+        };
+        return Error::from(kind).at(&text, span);
+    }
+
+    /// Builds an error for an expression that ended too early, located at its
+    /// last lexeme.
+    fn error_at_end(&self) -> Error {
+        let span = self.lexemes.last().map(|(_, span)| span.clone()).unwrap_or(0..0);
+        return self.error(ErrorKind::Syntax, span);
+    }
+
+    /// Compiles an expression whose binary operators bind at least as tightly
+    /// as `min`.
+    fn expr(&mut self, min: u8) -> Result<()> {
+        self.unary()?;
+        while let Some(Lexeme::Symbol(symbol)) = self.peek() {
+            let (op, precedence, right) = match binary(symbol) {
+                Some(b) if b.1 >= min => b,
+                _ => break,
+            };
+            let span = self.lexemes[self.pos].1.clone();
+            self.pos += 1;
+            self.expr(if right { precedence } else { precedence + 1 })?;
+            self.emit(op.word(), span);
+        }
+        return Ok(());
+    }
+
+    /// Compiles an operand, which may be negated, or, for booleans, have `not`
+    /// applied to it.
+    fn unary(&mut self) -> Result<()> {
+        let (op, precedence) = match self.peek() {
+            Some(Lexeme::Symbol("-")) => (Op::Neg, NEG_PRECEDENCE),
+            Some(Lexeme::Symbol("not")) => (Op::Not, NOT_PRECEDENCE),
+            _ => return self.primary(),
+        };
+        let span = self.lexemes[self.pos].1.clone();
+        self.pos += 1;
+        self.expr(precedence + 1)?;
+        self.emit(op.word(), span);
+        return Ok(());
+    }
+
+    /// Compiles a number, a variable, a call, or an expression in parentheses.
+    fn primary(&mut self) -> Result<()> {
+        let (lexeme, span) = match self.lexemes.get(self.pos) {
+            Some((lexeme, span)) => (lexeme, span.clone()),
+            None => return Err(self.error_at_end()),
+        };
+        self.pos += 1;
+
+        match lexeme {
+            Lexeme::Number(s) => {
+                if Elt::parse_number(s).is_none() {
+                    return Err(self.error(ErrorKind::Syntax, span));
+                }
+                let s = s.clone();
+                self.emit(&s, span);
+            }
+            Lexeme::Name(name) => {
+                let name = name.clone();
+                if let Some(Lexeme::Symbol("(")) = self.peek() {
+                    self.pos += 1;
+                    let count = self.arguments(span.clone())?;
+                    // Operators must be given as many arguments as they take.
+                    if Op::from_word(&name).and_then(arity).is_some_and(|n| n != count) {
+                        return Err(self.error(ErrorKind::Syntax, span));
+                    }
+                }
+                self.emit(&name, span);
+            }
+            Lexeme::Symbol("(") => {
+                self.expr(0)?;
+                self.close(span)?;
+            }
+            Lexeme::Symbol(")") => return Err(self.error(ErrorKind::Unbalanced, span)),
+            Lexeme::Symbol(_) => return Err(self.error(ErrorKind::Syntax, span)),
+        }
+        return Ok(());
+    }
+
+    /// Compiles the arguments of a call, separated by commas, up to the closing
+    /// parenthesis, giving how many there are. `open` is the span of the name
+    /// being called.
+    fn arguments(&mut self, open: Range<usize>) -> Result<usize> {
+        if let Some(Lexeme::Symbol(")")) = self.peek() {
+            self.pos += 1;
+            return Ok(0);
+        }
+        let mut count = 0;
+        loop {
+            self.expr(0)?;
+            count += 1;
+            match self.peek() {
+                Some(Lexeme::Symbol(",")) => self.pos += 1,
+                _ => {
+                    self.close(open)?;
+                    return Ok(count);
+                }
+            }
+        }
+    }
+
+    /// Expects a closing parenthesis, reporting the one at `open` as unclosed
+    /// if it is missing.
+    fn close(&mut self, open: Range<usize>) -> Result<()> {
+        return match self.peek() {
+            Some(Lexeme::Symbol(")")) => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.error(ErrorKind::Syntax, self.lexemes[self.pos].1.clone())),
+            None => Err(self.error(ErrorKind::Unbalanced, open)),
+        };
+    }
+}
+
+/// Prints compiled tokens as the line of RPN they make up.
+pub fn to_rpn(tokens: &[(Token, Range<usize>)]) -> String {
+    let words: Vec<String> = tokens.iter().map(|(token, _)| token.to_string()).collect();
+    return words.join(" ");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpn(line: &str) -> String {
+        return to_rpn(&compile(line).unwrap());
+    }
+
+    #[test]
+    fn test_compile_precedence() {
+        assert_eq!(rpn("(3 + 4) * 2"), "3 4 + 2 *");
+        assert_eq!(rpn("3 + 4 * 2"), "3 4 2 * +");
+        assert_eq!(rpn("10 - 4 - 3"), "10 4 - 3 -");
+        assert_eq!(rpn("2 ^ 3 ^ 2"), "2 3 2 pow pow");
+        assert_eq!(rpn("-2 ^ 2"), "2 2 pow ~");
+        assert_eq!(rpn("-x * -(1 - y)"), "x ~ 1 y - ~ *");
+        assert_eq!(rpn("1 + 2 == 3"), "1 2 + 3 =");
+        assert_eq!(rpn("7 % 4 / 2"), "7 4 % 2 /");
+        assert_eq!(rpn("x + 1 >= 2 * y"), "x 1 + 2 y * >=");
+        assert_eq!(rpn("a < b and b != c or not d"), "a b < b c != and d not or");
+        assert_eq!(rpn("not x == y and z"), "x y = not z and");
+        assert_eq!(rpn("p or q xor r"), "p q or r xor");
+        assert_eq!(rpn(""), "");
+    }
+
+    #[test]
+    fn test_compile_calls() {
+        assert_eq!(rpn("max(1, 2 * x) + sqrt(16)"), "1 2 x * max 16 sqrt +");
+        assert_eq!(rpn("-x ^ 2 + max(1, y) * 3"), "x 2 pow ~ 1 y max 3 * +");
+        assert_eq!(rpn("depth()"), "depth");
+        assert_eq!(rpn("1.5e-3 * 2i"), "1.5e-3 2i *");
+        assert_eq!(rpn("band(0xFF, x) + 0b10 * 0o7"), "0xFF x band 0b10 0o7 * +");
+        // Words other than operators may take any number of arguments.
+        assert_eq!(rpn("f(1, 2, 3) + pick(x, 1)"), "1 2 3 f x 1 pick +");
+    }
+
+    fn error(line: &str) -> (ErrorKind, Option<String>, Option<Range<usize>>) {
+        let e = compile(line).unwrap_err();
+        let (token, span) = (e.token().map(String::from), e.span());
+        return (e.kind, token, span);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let syntax = |line: &str, token: &str, span: Range<usize>| {
+            let (kind, t, s) = error(line);
+            if let ErrorKind::Syntax = kind {} else { assert!(false); }
+            assert_eq!((t, s), (Some(token.to_string()), Some(span)));
+        };
+        syntax("1 +", "+", 2..3);
+        syntax("1 2", "2", 2..3);
+        syntax("1 + * 2", "*", 4..5);
+        syntax("1 $ 2", "$", 2..3);
+        syntax("1.2.3 + 1", "1.2.3", 0..5);
+        syntax("max(1 2)", "2", 6..7);
+        syntax("1 ! 2", "!", 2..3);
+        syntax("0x1G + 1", "0x1G", 0..4);
+        syntax("1 and", "and", 2..5);
+        syntax("max(1)", "max", 0..3);
+        syntax("1 + sqrt(1, 2, 3)", "sqrt", 4..8);
+        syntax("depth(1)", "depth", 0..5);
+
+        let unbalanced = |line: &str, token: &str, span: Range<usize>| {
+            let (kind, t, s) = error(line);
+            if let ErrorKind::Unbalanced = kind {} else { assert!(false); }
+            assert_eq!((t, s), (Some(token.to_string()), Some(span)));
+        };
+        unbalanced("(1 + 2", "(", 0..1);
+        unbalanced("1 + 2)", ")", 5..6);
+        unbalanced("max(1, 2", "max", 0..3);
+    }
+}
@@ -0,0 +1,107 @@
+use std::env;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::rpn::{ErrorKind, Result};
+
+/// The file in the home directory that history is kept in between sessions.
+const HISTORY_FILE: &str = ".hw04_history";
+
+// Input
+// Where the read-eval-print loop gets its lines from: a line editor with
+// cursor movement, history navigation and reverse search when a person is
+// typing, or a plain reader otherwise. Either way, the lines read are kept as
+// history, which the editor also saves to a file.
+pub struct Input<R: BufRead> {
+    reader: R,
+    editor: Option<DefaultEditor>,
+    path: Option<PathBuf>,
+    history: Vec<String>,
+}
+
+impl<R: BufRead> Input<R> {
+    /// Reads from a line editor on the terminal if `interactive`, loading the
+    /// history saved by earlier sessions, or from `reader` if not.
+    pub fn new(reader: R, interactive: bool) -> Input<R> {
+        let mut input = Input { reader, editor: None, path: None, history: vec![] };
+        if !interactive {
+            return input;
+        }
+        // Without a terminal to edit on, fall back to the reader.
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(_) => return input,
+        };
+        input.path = env::home_dir().map(|home| home.join(HISTORY_FILE));
+        if let Some(path) = &input.path {
+            // There is no history yet the first time around.
+            let _ = editor.load_history(path);
+            input.history = editor.history().iter().cloned().collect();
+        }
+        input.editor = Some(editor);
+        return input;
+    }
+
+    /// Reads a line, showing `prompt` if a person is typing. Gives None at the
+    /// end of the input. Interrupting a line with Ctrl-C abandons it.
+    pub fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+        let editor = match &mut self.editor {
+            Some(editor) => editor,
+            None => {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line).map_err(ErrorKind::IO)? == 0 {
+                    return Ok(None);
+                }
+                return Ok(Some(line));
+            }
+        };
+        return match editor.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(ReadlineError::Io(e)) => Err(ErrorKind::IO(e).into()),
+            Err(e) => Err(ErrorKind::IO(io::Error::other(e)).into()),
+        };
+    }
+
+    /// Adds a line to the history, saving it straight away so that it is not
+    /// lost if the session ends with an error. Blank lines are left out.
+    pub fn remember(&mut self, line: &str) {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            return;
+        }
+        self.history.push(line.to_string());
+        if let Some(editor) = &mut self.editor {
+            // History is only a convenience, so failing to save it, say to a
+            // read-only home directory, is not worth stopping for.
+            let _ = editor.add_history_entry(line);
+            if let Some(path) = &self.path {
+                let _ = editor.save_history(path);
+            }
+        }
+    }
+
+    /// The lines read so far, oldest first, including those from earlier
+    /// sessions.
+    pub fn history(&self) -> &[String] {
+        return &self.history;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remember() {
+        let mut input = Input::new(io::empty(), false);
+        input.remember("1 2 +\n");
+        input.remember("  \n");
+        input.remember("history 1");
+        assert_eq!(input.history(), ["1 2 +", "history 1"]);
+    }
+}
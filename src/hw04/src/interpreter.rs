@@ -0,0 +1,170 @@
+use std::io::{self, Write};
+use std::result;
+
+use crate::check::{check_line, check_script, Summary};
+use crate::env::Env;
+use crate::parser::{evaluate_infix, evaluate_line, evaluate_script};
+use crate::rpn::{Error, Result, Stack};
+use crate::session::Session;
+
+// Interpreter
+// The calculator, ready to embed in another program: a stack to work on, an
+// environment to hold variables and words, and somewhere to write output, such
+// as the listing from `words`, to.
+pub struct Interpreter<W: Write = io::Stdout> {
+    stack: Stack,
+    env: Env,
+    out: W,
+}
+
+impl Interpreter {
+    /// Creates an interpreter which writes its output to stdout.
+    pub fn new() -> Interpreter {
+        return Interpreter::default();
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        return Interpreter::with_output(io::stdout());
+    }
+}
+
+impl<W: Write> Interpreter<W> {
+    /// Creates an interpreter which writes its output to `out`.
+    pub fn with_output(out: W) -> Interpreter<W> {
+        return Interpreter { stack: Stack::new(), env: Env::new(), out };
+    }
+
+    /// Evaluates RPN, such as `1 2 +`, one line at a time, stopping at the
+    /// first error.
+    pub fn eval_str(&mut self, source: &str) -> Result<()> {
+        for line in source.lines() {
+            evaluate_line(&mut self.stack, &mut self.env, &mut self.out, line)?;
+        }
+        return Ok(());
+    }
+
+    /// Evaluates an infix expression, such as `(1 + 2) * 3`.
+    pub fn eval_infix(&mut self, expr: &str) -> Result<()> {
+        return evaluate_infix(&mut self.stack, &mut self.env, &mut self.out, expr);
+    }
+
+    /// Evaluates a script, which may start with a `#!` line. Errors come with
+    /// the number of their line, counting from 1. The script is checked first,
+    /// so that errors it is certain to hit are found before any of it runs.
+    pub fn eval_script(&mut self, script: &str) -> result::Result<(), (usize, Error)> {
+        check_script(&self.stack, &self.env, script)?;
+        return evaluate_script(&mut self.stack, &mut self.env, &mut self.out, script);
+    }
+
+    /// Checks a line of RPN without running it, failing if running it is
+    /// certain to fail, and otherwise giving its effect on the stack.
+    pub fn check(&self, line: &str) -> Result<Summary> {
+        return check_line(&self.stack, &self.env, line);
+    }
+
+    /// The stack that lines are evaluated on.
+    pub fn stack(&self) -> &Stack {
+        return &self.stack;
+    }
+
+    /// The stack that lines are evaluated on, to push arguments or pop results.
+    pub fn stack_mut(&mut self) -> &mut Stack {
+        return &mut self.stack;
+    }
+
+    /// The variables and words defined so far.
+    pub fn env(&self) -> &Env {
+        return &self.env;
+    }
+
+    /// Where output is written.
+    pub fn output(&self) -> &W {
+        return &self.out;
+    }
+
+    /// Where output is written, to write more to it.
+    pub fn output_mut(&mut self) -> &mut W {
+        return &mut self.out;
+    }
+
+    /// Marks the end of a line that succeeded, recording its changes to the
+    /// stack as one step that can be undone.
+    pub fn checkpoint(&mut self) {
+        self.stack.checkpoint();
+        self.env.checkpoint();
+    }
+
+    /// Discards the changes to the stack, variables and words since the last
+    /// checkpoint, so that a line which fails part way leaves no trace.
+    pub fn rollback(&mut self) {
+        self.stack.rollback();
+        self.env.rollback();
+    }
+
+    /// Takes a copy of the stack, variables and words.
+    pub fn session(&self) -> Session {
+        return Session::capture(&self.stack, &self.env);
+    }
+
+    /// Replaces the stack, variables and words with a session's, as one step
+    /// that can be undone.
+    pub fn restore(&mut self, session: Session) -> Result<()> {
+        session.restore(&mut self.stack, &mut self.env)?;
+        self.checkpoint();
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpn::{Elt, ErrorKind};
+
+    #[test]
+    fn test_eval_str() {
+        let mut interp = Interpreter::with_output(vec![]);
+        assert!(interp.eval_str(": sq dup * ;\n3 sq").is_ok());
+        assert!(interp.eval_infix("sq(4) + 1").is_ok());
+        assert_eq!(interp.stack().values(), [Elt::Int(9), Elt::Int(17)]);
+        assert_eq!(interp.stack_mut().pop().unwrap(), Elt::Int(17));
+
+        assert!(interp.eval_str("words").is_ok());
+        assert_eq!(String::from_utf8_lossy(interp.output()), ": sq dup * ;\n");
+
+        let res = interp.eval_str("1\n2 +\n\"a\" +");
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        let res = interp.eval_script("#!/usr/bin/env hw04\n1\n0 /");
+        if let Err((3, Error { kind: ErrorKind::DivideByZero, .. })) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_session() {
+        let mut interp = Interpreter::with_output(io::sink());
+        assert!(interp.eval_str("1 2 'x store").is_ok());
+        let session = interp.session();
+
+        let mut other = Interpreter::with_output(io::sink());
+        assert!(other.restore(session).is_ok());
+        assert!(other.eval_str("x +").is_ok());
+        assert_eq!(other.stack().values(), [Elt::Int(3)]);
+        assert!(other.stack_mut().undo());
+        assert!(other.stack_mut().undo());
+        assert!(other.stack().is_empty());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut interp = Interpreter::with_output(io::sink());
+        assert!(interp.eval_str("1 'y store : g 2 ;").is_ok());
+        interp.checkpoint();
+        assert!(interp.eval_str("5 'x store 2 'y store : f 1 ; 'g forget 1 0 /").is_err());
+        interp.rollback();
+        assert!(interp.env().recall("x").is_err());
+        assert_eq!(interp.env().recall("y").unwrap(), Elt::Int(1));
+        assert!(interp.env().word("f").is_none());
+        assert!(interp.env().word("g").is_some());
+        assert!(interp.stack().is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::CharIndices;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rpn::{ErrorKind, Result};
+
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Serialize, Deserialize)]
+/// A token of source code.
+pub enum Token {
+    /// A bare word, such as `12`, `+` or `if`.
+    Word(String),
+    /// A quoted string literal, with its escapes already resolved.
+    Str(String),
+}
+
+// Tokens print as they would be written, so that word definitions can be
+// listed back to the user.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Token::Word(s) => write!(f, "{}", s),
+            Token::Str(s) => write!(f, "{:?}", s),
+        };
+    }
+}
+
+/// Splits a line into tokens, each with the span of columns it covers. Words
+/// are separated by whitespace, and string literals are wrapped in double
+/// quotes, so they may contain spaces. A `\` word starts a comment, which runs
+/// to the end of the line, as in Forth.
+pub fn tokenize(line: &str) -> Result<Vec<(Token, Range<usize>)>> {
+    let mut tokens = vec![];
+    let mut chars = line.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let token = if c == '"' {
+            chars.next();
+            lex_string(&mut chars).map(Token::Str)
+        } else {
+            lex_word(line, start, &mut chars).map(Token::Word)
+        };
+        // Spans count characters, so that they line up with what was typed.
+        let end = chars.peek().map_or(line.len(), |&(i, _)| i);
+        let text = line[start..end].trim_end();
+        let col = line[..start].chars().count();
+        let span = col..col + text.chars().count();
+        match token {
+            Ok(Token::Word(w)) if w == "\\" => break,
+            Ok(token) => tokens.push((token, span)),
+            Err(e) => return Err(e.at(text, span)),
+        }
+    }
+    return Ok(tokens);
+}
+
+/// Reads a bare word, up to the next whitespace.
+fn lex_word(line: &str, start: usize, chars: &mut Peekable<CharIndices>) -> Result<String> {
+    let mut end = line.len();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            end = i;
+            break;
+        }
+        chars.next();
+        // Quotes may only start a token, never appear inside one.
+        if c == '"' {
+            return Err(ErrorKind::Syntax.into());
+        }
+    }
+    return Ok(line[start..end].to_string());
+}
+
+/// Reads the rest of a string literal, after its opening quote.
+fn lex_string(chars: &mut Peekable<CharIndices>) -> Result<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '"')) => break,
+            Some((_, '\\')) => s.push(match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 't')) => '\t',
+                Some((_, 'r')) => '\r',
+                Some((_, '0')) => '\0',
+                Some((_, '\\')) => '\\',
+                Some((_, '"')) => '"',
+                _ => return Err(ErrorKind::Syntax.into()),
+            }),
+            Some((_, c)) => s.push(c),
+            // The line ended before the closing quote.
+            None => return Err(ErrorKind::Syntax.into()),
+        }
+    }
+
+    // A literal must be followed by whitespace, just like a word.
+    return match chars.peek() {
+        Some((_, c)) if !c.is_whitespace() => {
+            chars.next();
+            Err(ErrorKind::Syntax.into())
+        }
+        _ => Ok(s),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpn::Error;
+
+    fn word(s: &str) -> Token {
+        return Token::Word(s.to_string());
+    }
+
+    fn string(s: &str) -> Token {
+        return Token::Str(s.to_string());
+    }
+
+    fn tokens(line: &str) -> Vec<Token> {
+        return tokenize(line).unwrap().into_iter().map(|(token, _)| token).collect();
+    }
+
+    #[test]
+    fn test_tokenize_words() {
+        assert_eq!(tokens("  1 2\t+ \n"), vec![word("1"), word("2"), word("+")]);
+        assert_eq!(tokens(""), vec![]);
+    }
+
+    #[test]
+    fn test_tokenize_strings() {
+        assert_eq!(tokens("\"hello world\" len"), vec![string("hello world"), word("len")]);
+        assert_eq!(tokens("\"\""), vec![string("")]);
+        assert_eq!(tokens(r#""a\"b\\c\n""#), vec![string("a\"b\\c\n")]);
+    }
+
+    #[test]
+    fn test_tokenize_bad_strings() {
+        for s in &["\"open", "\"bad \\q escape\"", "\"a\"b", "a\"b\"", "\"trailing\\"] {
+            let res = tokenize(s);
+            assert!(res.is_err());
+            if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+        }
+    }
+
+    #[test]
+    fn test_token_display() {
+        assert_eq!(word("+").to_string(), "+");
+        assert_eq!(string("a \"b\"\n").to_string(), r#""a \"b\"\n""#);
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let spans: Vec<Range<usize>> = tokenize(" 12 \"é x\"\t+\n").unwrap().into_iter().map(|(_, span)| span).collect();
+        assert_eq!(spans, vec![1..3, 4..9, 10..11]);
+    }
+
+    #[test]
+    fn test_tokenize_error_spans() {
+        for (line, token, span) in [("1 \"open  \n", "\"open", 2..7), ("a\"b c", "a\"", 0..2), ("é \"x\"y", "\"x\"y", 2..6)] {
+            let e = tokenize(line).unwrap_err();
+            assert_eq!((e.token(), e.span()), (Some(token), Some(span)));
+        }
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        assert_eq!(tokens("1 2 + \\ add \"them\" up"), vec![word("1"), word("2"), word("+")]);
+        assert_eq!(tokens("\\ 1"), vec![]);
+        assert_eq!(tokens("\"a \\\\ b\" \\\\ \\x"), vec![string("a \\ b"), word("\\\\"), word("\\x")]);
+    }
+}
@@ -0,0 +1,12 @@
+pub mod check;
+pub mod env;
+pub mod infix;
+pub mod input;
+pub mod interpreter;
+pub mod lexer;
+pub mod num;
+pub mod parser;
+pub mod repl;
+pub mod rpn;
+pub mod session;
+pub mod vm;
@@ -1,10 +1,77 @@
-pub mod parser;
-pub mod rpn;
+use std::env::args;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::process;
 
-use parser::read_eval_print_loop;
+use hw04::input::Input;
+use hw04::interpreter::Interpreter;
+use hw04::repl::{read_eval_print_loop, run_script};
+use hw04::rpn::{ErrorKind, UNDO_DEPTH};
+use hw04::session::Session;
+
+fn usage() -> ! {
+    eprintln!("Usage: hw04 [--undo-depth N] [--load session.json] [script.rpn | -e expression]");
+    process::exit(2);
+}
 
 fn main() {
-    if let Err(err) = read_eval_print_loop() {
-        println!("Error: {:?}", err);
+    let args: Vec<String> = args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let mut undo_depth = UNDO_DEPTH;
+    let mut session = None;
+    loop {
+        match args.as_slice() {
+            ["--undo-depth", n, ..] => undo_depth = n.parse().unwrap_or_else(|_| usage()),
+            // Start from a saved session, such as one handed off by someone else.
+            ["--load", path, ..] => match Session::load(path) {
+                Ok(s) => session = Some(s),
+                Err(err) => {
+                    eprintln!("Error: {}: {}", path, err);
+                    process::exit(1);
+                }
+            },
+            _ => break,
+        }
+        args.drain(..2);
+    }
+
+    let mut interp = Interpreter::new();
+    interp.stack_mut().set_undo_depth(undo_depth);
+    if let Some(session) = session {
+        if let Err(err) = interp.restore(session) {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+
+    // With no arguments, read lines from stdin, prompting only if a person is
+    // typing them. Lines piped in from elsewhere fail like a script would, so
+    // the exit status says whether any of them did. Otherwise, run a script
+    // file or a single expression.
+    let res = match args.as_slice() {
+        [] => {
+            let stdin = io::stdin();
+            let interactive = stdin.is_terminal();
+            let mut input = Input::new(stdin.lock(), interactive);
+            match read_eval_print_loop(&mut interp, &mut input, &mut io::stderr()) {
+                Ok(false) if !interactive => process::exit(1),
+                res => res.map(|_| ()),
+            }
+        }
+        ["-e", expr] => run_script(&mut interp, expr, &mut io::stderr()),
+        [path] if !path.starts_with('-') => match fs::read_to_string(path) {
+            Ok(script) => run_script(&mut interp, &script, &mut io::stderr()),
+            Err(e) => Err(ErrorKind::IO(e).into()),
+        },
+        _ => usage(),
+    };
+
+    if let Err(err) = res {
+        if let ErrorKind::Quit = err.kind {
+            return;
+        }
+        eprintln!("Error: {}", err);
+        process::exit(1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,496 @@
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+
+use crate::rpn::{Elt, ErrorKind, Result};
+
+/// The largest result, in bits, that `pow` will compute before giving up.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+// Pair
+// Two numbers promoted to a common representation, so that they can be operated
+// on together. Integers are promoted to big integers, both to rationals, all of
+// them to floats, and floats to complex numbers. Only floats and complex numbers
+// are inexact.
+pub enum Pair {
+    Int(i32, i32),
+    Big(BigInt, BigInt),
+    Rational(BigRational, BigRational),
+    Float(f64, f64),
+    Complex(Complex64, Complex64),
+}
+
+/// Builds an integer element, using Int when the value fits and Big otherwise,
+/// so that every integer has exactly one representation.
+pub fn int(b: BigInt) -> Elt {
+    return match b.to_i32() {
+        Some(i) => Elt::Int(i),
+        None => Elt::Big(b),
+    };
+}
+
+/// Builds a rational element, which is an integer if the denominator is 1.
+/// Ratios are always kept in lowest terms, with a positive denominator.
+pub fn rational(r: BigRational) -> Elt {
+    return if r.is_integer() { int(r.to_integer()) } else { Elt::Rational(r) };
+}
+
+/// Converts an integer element to a big integer.
+fn to_big(e: &Elt) -> Option<BigInt> {
+    return match e {
+        Elt::Int(i) => Some(BigInt::from(*i)),
+        Elt::Big(b) => Some(b.clone()),
+        _ => None,
+    };
+}
+
+/// Converts an exact number to a rational.
+fn to_rational(e: &Elt) -> Option<BigRational> {
+    return match e {
+        Elt::Rational(r) => Some(r.clone()),
+        e => Some(BigRational::from_integer(to_big(e)?)),
+    };
+}
+
+/// Converts a number to a float, promoting exact numbers. Those beyond the
+/// range of a float become infinite.
+pub fn to_float(e: &Elt) -> Option<f64> {
+    let inf = |negative: bool| if negative { f64::NEG_INFINITY } else { f64::INFINITY };
+    return match e {
+        Elt::Int(i) => Some(*i as f64),
+        Elt::Big(b) => Some(b.to_f64().unwrap_or(inf(b.is_negative()))),
+        Elt::Rational(r) => Some(r.to_f64().unwrap_or(inf(r.is_negative()))),
+        Elt::Float(f) => Some(*f),
+        _ => None,
+    };
+}
+
+/// Converts a number to a complex number, promoting real numbers to floats.
+pub fn to_complex(e: &Elt) -> Option<Complex64> {
+    return match e {
+        Elt::Complex(z) => Some(*z),
+        e => Some(Complex64::new(to_float(e)?, 0.0)),
+    };
+}
+
+/// Promotes two numbers to a common representation. Fails if either is not a
+/// number.
+pub fn promote(y: &Elt, x: &Elt) -> Result<Pair> {
+    return match (y, x) {
+        (Elt::Int(y), Elt::Int(x)) => Ok(Pair::Int(*y, *x)),
+        (Elt::Complex(_), _) | (_, Elt::Complex(_)) => match (to_complex(y), to_complex(x)) {
+            (Some(y), Some(x)) => Ok(Pair::Complex(y, x)),
+            _ => Err(ErrorKind::Type.into()),
+        },
+        (Elt::Float(_), _) | (_, Elt::Float(_)) => match (to_float(y), to_float(x)) {
+            (Some(y), Some(x)) => Ok(Pair::Float(y, x)),
+            _ => Err(ErrorKind::Type.into()),
+        },
+        (Elt::Rational(_), _) | (_, Elt::Rational(_)) => match (to_rational(y), to_rational(x)) {
+            (Some(y), Some(x)) => Ok(Pair::Rational(y, x)),
+            _ => Err(ErrorKind::Type.into()),
+        },
+        _ => match (to_big(y), to_big(x)) {
+            (Some(y), Some(x)) => Ok(Pair::Big(y, x)),
+            _ => Err(ErrorKind::Type.into()),
+        },
+    };
+}
+
+/// Compares two floats, treating -0.0 as equal to 0.0 and every NaN as equal
+/// to each other and greater than all other numbers, so that the order is total.
+fn cmp_float(x: f64, y: f64) -> Ordering {
+    let canonical = |f: f64| if f.is_nan() { f64::NAN } else if f == 0.0 { 0.0 } else { f };
+    return canonical(x).total_cmp(&canonical(y));
+}
+
+/// Compares an exact number with a float exactly, without rounding the number.
+fn cmp_exact_float(r: &BigRational, f: f64) -> Ordering {
+    if f.is_nan() || f == f64::INFINITY {
+        return Ordering::Less;
+    }
+    if f == f64::NEG_INFINITY {
+        return Ordering::Greater;
+    }
+    // Every finite float is exactly a rational.
+    return r.cmp(&BigRational::from_float(f).unwrap());
+}
+
+/// Compares two numbers by value, regardless of representation. Returns None
+/// if either is not a number. Complex numbers have no natural order, so they
+/// are ordered by their real parts and then by their imaginary parts. Real
+/// parts are compared exactly, as real numbers are, so that the order stays
+/// consistent between them.
+pub fn cmp(y: &Elt, x: &Elt) -> Option<Ordering> {
+    return match (y, x) {
+        (Elt::Int(y), Elt::Int(x)) => Some(y.cmp(x)),
+        (Elt::Complex(y), Elt::Complex(x)) => Some(cmp_float(y.re, x.re).then(cmp_float(y.im, x.im))),
+        (Elt::Complex(y), x) => Some(cmp(&Elt::Float(y.re), x)?.then(cmp_float(y.im, 0.0))),
+        (y, Elt::Complex(x)) => Some(cmp(y, &Elt::Float(x.re))?.then(cmp_float(0.0, x.im))),
+        (Elt::Float(y), Elt::Float(x)) => Some(cmp_float(*y, *x)),
+        (Elt::Float(y), x) => Some(cmp_exact_float(&to_rational(x)?, *y).reverse()),
+        (y, Elt::Float(x)) => Some(cmp_exact_float(&to_rational(y)?, *x)),
+        _ => Some(to_rational(y)?.cmp(&to_rational(x)?)),
+    };
+}
+
+pub fn add(p: Pair) -> Result<Elt> {
+    return Ok(match p {
+        Pair::Int(y, x) => match y.checked_add(x) {
+            Some(i) => Elt::Int(i),
+            None => int(BigInt::from(y) + x),
+        },
+        Pair::Big(y, x) => int(y + x),
+        Pair::Rational(y, x) => rational(y + x),
+        Pair::Float(y, x) => Elt::Float(y + x),
+        Pair::Complex(y, x) => Elt::Complex(y + x),
+    });
+}
+
+pub fn sub(p: Pair) -> Result<Elt> {
+    return Ok(match p {
+        Pair::Int(y, x) => match y.checked_sub(x) {
+            Some(i) => Elt::Int(i),
+            None => int(BigInt::from(y) - x),
+        },
+        Pair::Big(y, x) => int(y - x),
+        Pair::Rational(y, x) => rational(y - x),
+        Pair::Float(y, x) => Elt::Float(y - x),
+        Pair::Complex(y, x) => Elt::Complex(y - x),
+    });
+}
+
+pub fn mul(p: Pair) -> Result<Elt> {
+    return Ok(match p {
+        Pair::Int(y, x) => match y.checked_mul(x) {
+            Some(i) => Elt::Int(i),
+            None => int(BigInt::from(y) * x),
+        },
+        Pair::Big(y, x) => int(y * x),
+        Pair::Rational(y, x) => rational(y * x),
+        Pair::Float(y, x) => Elt::Float(y * x),
+        Pair::Complex(y, x) => Elt::Complex(y * x),
+    });
+}
+
+/// Divides exactly, so dividing integers gives a rational unless the division
+/// happens to be exact.
+pub fn div(p: Pair) -> Result<Elt> {
+    return match p {
+        Pair::Int(_, 0) => Err(ErrorKind::DivideByZero.into()),
+        Pair::Int(y, x) if y.wrapping_rem(x) == 0 => Ok(match y.checked_div(x) {
+            Some(i) => Elt::Int(i),
+            // Only i32::MIN / -1 overflows.
+            None => int(-BigInt::from(y)),
+        }),
+        Pair::Int(y, x) => Ok(rational(BigRational::new(y.into(), x.into()))),
+        Pair::Big(_, x) if x.is_zero() => Err(ErrorKind::DivideByZero.into()),
+        Pair::Big(y, x) => Ok(rational(BigRational::new(y, x))),
+        Pair::Rational(_, x) if x.is_zero() => Err(ErrorKind::DivideByZero.into()),
+        Pair::Rational(y, x) => Ok(rational(y / x)),
+        Pair::Float(y, x) => Ok(Elt::Float(y / x)),
+        Pair::Complex(y, x) => Ok(Elt::Complex(y / x)),
+    };
+}
+
+/// Takes the remainder of dividing, rounding the quotient towards zero, so
+/// the remainder has the same sign as the dividend.
+pub fn rem(p: Pair) -> Result<Elt> {
+    return match p {
+        Pair::Int(_, 0) => Err(ErrorKind::DivideByZero.into()),
+        // i32::MIN % -1 is mathematically 0, which is what wrapping gives.
+        Pair::Int(y, x) => Ok(Elt::Int(y.wrapping_rem(x))),
+        Pair::Big(_, x) if x.is_zero() => Err(ErrorKind::DivideByZero.into()),
+        Pair::Big(y, x) => Ok(int(y % x)),
+        Pair::Rational(_, x) if x.is_zero() => Err(ErrorKind::DivideByZero.into()),
+        Pair::Rational(y, x) => Ok(rational(&y - &x * (&y / &x).trunc())),
+        Pair::Float(y, x) => Ok(Elt::Float(y % x)),
+        // Complex numbers cannot be rounded towards zero.
+        Pair::Complex(_, _) => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Raises to a power. Exact numbers stay exact when raised to an integer
+/// power, and become floats when raised to a fractional one.
+pub fn pow(p: Pair) -> Result<Elt> {
+    return match p {
+        Pair::Int(y, x) if x >= 0 => match y.checked_pow(x as u32) {
+            Some(i) => Ok(Elt::Int(i)),
+            None => pow_exact(BigRational::from_integer(y.into()), BigInt::from(x)),
+        },
+        Pair::Int(y, x) => pow_exact(BigRational::from_integer(y.into()), BigInt::from(x)),
+        Pair::Big(y, x) => pow_exact(BigRational::from_integer(y), x),
+        Pair::Rational(y, x) if x.is_integer() => pow_exact(y, x.to_integer()),
+        Pair::Rational(y, x) => Ok(Elt::Float(to_float(&Elt::Rational(y)).unwrap().powf(to_float(&Elt::Rational(x)).unwrap()))),
+        Pair::Float(y, x) => Ok(Elt::Float(y.powf(x))),
+        Pair::Complex(y, x) => Ok(Elt::Complex(y.powc(x))),
+    };
+}
+
+fn pow_exact(y: BigRational, x: BigInt) -> Result<Elt> {
+    // 0, 1 and -1 stay small no matter the power.
+    if x.is_zero() || y.is_one() {
+        return Ok(Elt::Int(1));
+    }
+    if y.is_zero() {
+        return if x.is_negative() { Err(ErrorKind::DivideByZero.into()) } else { Ok(Elt::Int(0)) };
+    }
+    if y.abs().is_one() {
+        return Ok(Elt::Int(if x.bit(0) { -1 } else { 1 }));
+    }
+    // Otherwise, refuse to build a result that would exhaust memory.
+    let bits = y.numer().bits().max(y.denom().bits());
+    let n = match x.abs().to_u64() {
+        Some(n) if n.saturating_mul(bits) <= MAX_POW_BITS => n as i32,
+        _ => return Err(ErrorKind::Overflow.into()),
+    };
+    return Ok(rational(if x.is_negative() { y.pow(-n) } else { y.pow(n) }));
+}
+
+pub fn neg(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Int(i) => Ok(match i.checked_neg() {
+            Some(i) => Elt::Int(i),
+            None => int(-BigInt::from(i)),
+        }),
+        Elt::Big(b) => Ok(int(-b)),
+        Elt::Rational(r) => Ok(Elt::Rational(-r)),
+        Elt::Float(f) => Ok(Elt::Float(-f)),
+        Elt::Complex(z) => Ok(Elt::Complex(-z)),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+pub fn abs(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Int(i) => Ok(match i.checked_abs() {
+            Some(i) => Elt::Int(i),
+            None => int(BigInt::from(i).abs()),
+        }),
+        Elt::Big(b) => Ok(int(b.abs())),
+        Elt::Rational(r) => Ok(Elt::Rational(r.abs())),
+        Elt::Float(f) => Ok(Elt::Float(f.abs())),
+        Elt::Complex(z) => Ok(Elt::Float(z.norm())),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Takes the square root of a number. Negative numbers have imaginary roots.
+pub fn sqrt(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Complex(z) => Ok(Elt::Complex(z.sqrt())),
+        e => match to_float(&e) {
+            Some(f) if f < 0.0 => Ok(Elt::Complex(Complex64::new(0.0, (-f).sqrt()))),
+            Some(f) => Ok(Elt::Float(f.sqrt())),
+            None => Err(ErrorKind::Type.into()),
+        },
+    };
+}
+
+/// Takes the real part of a number. Real numbers are their own real part.
+pub fn re(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Complex(z) => Ok(Elt::Float(z.re)),
+        e @ Elt::Int(_) | e @ Elt::Big(_) | e @ Elt::Rational(_) | e @ Elt::Float(_) => Ok(e),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Takes the imaginary part of a number, which is 0 for real numbers.
+pub fn im(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Complex(z) => Ok(Elt::Float(z.im)),
+        Elt::Int(_) | Elt::Big(_) | Elt::Rational(_) => Ok(Elt::Int(0)),
+        Elt::Float(_) => Ok(Elt::Float(0.0)),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Takes the complex conjugate of a number. Real numbers are their own
+/// conjugate.
+pub fn conj(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Complex(z) => Ok(Elt::Complex(z.conj())),
+        e @ Elt::Int(_) | e @ Elt::Big(_) | e @ Elt::Rational(_) | e @ Elt::Float(_) => Ok(e),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Rounds a number to an integer using f for floats and r for rationals.
+/// Floats stay floats, since they may be too large or not finite.
+pub fn round(e: Elt, f: fn(f64) -> f64, r: fn(&BigRational) -> BigRational) -> Result<Elt> {
+    return match e {
+        Elt::Float(x) => Ok(Elt::Float(f(x))),
+        Elt::Rational(x) => Ok(rational(r(&x))),
+        e @ Elt::Int(_) | e @ Elt::Big(_) => Ok(e),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Converts a number to an integer, rounding towards zero.
+pub fn to_int(e: Elt) -> Result<Elt> {
+    return match e {
+        // NaN and the infinities have no integer value.
+        Elt::Float(f) => BigInt::from_f64(f.trunc()).map(int).ok_or_else(|| ErrorKind::Overflow.into()),
+        Elt::Rational(r) => Ok(int(r.to_integer())),
+        e @ Elt::Int(_) | e @ Elt::Big(_) => Ok(e),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Takes the numerator of an exact number, in lowest terms.
+pub fn numer(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Rational(r) => Ok(int(r.numer().clone())),
+        e @ Elt::Int(_) | e @ Elt::Big(_) => Ok(e),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Takes the denominator of an exact number, in lowest terms.
+pub fn denom(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Rational(r) => Ok(int(r.denom().clone())),
+        Elt::Int(_) | Elt::Big(_) => Ok(Elt::Int(1)),
+        _ => Err(ErrorKind::Type.into()),
+    };
+}
+
+/// Shifts an integer left by n bits, refusing to build a result that would
+/// exhaust memory.
+pub fn shl(x: BigInt, n: u32) -> Result<Elt> {
+    if !x.is_zero() && x.bits().saturating_add(n as u64) > MAX_POW_BITS {
+        return Err(ErrorKind::Overflow.into());
+    }
+    return Ok(int(x << n as usize));
+}
+
+/// Shifts an integer right by n bits, rounding towards negative infinity, as
+/// shifting a two's complement integer does.
+pub fn shr(x: BigInt, n: u32) -> Result<Elt> {
+    return Ok(int(x >> n as usize));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpn::Error;
+
+    fn big(s: &str) -> BigInt {
+        return s.parse().unwrap();
+    }
+
+    #[test]
+    fn test_shift() {
+        assert_eq!(shl(BigInt::from(1), 31).unwrap(), Elt::Big(big("2147483648")));
+        assert_eq!(shl(BigInt::from(-3), 2).unwrap(), Elt::Int(-12));
+        assert_eq!(shl(BigInt::from(0), u32::MAX).unwrap(), Elt::Int(0));
+        assert_eq!(shr(big("-2147483649"), 1).unwrap(), Elt::Int(-1073741825));
+        assert_eq!(shr(BigInt::from(-1), 100).unwrap(), Elt::Int(-1));
+        if let Err(Error { kind: ErrorKind::Overflow, .. }) = shl(BigInt::from(1), u32::MAX) {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_int_normalises() {
+        if let Elt::Int(5) = int(BigInt::from(5)) {} else { assert!(false); }
+        if let Elt::Int(i32::MIN) = int(BigInt::from(i32::MIN)) {} else { assert!(false); }
+        if let Elt::Big(_) = int(BigInt::from(i32::MAX) + 1) {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_promote() {
+        if let Ok(Pair::Int(1, 2)) = promote(&Elt::Int(1), &Elt::Int(2)) {} else { assert!(false); }
+        if let Ok(Pair::Big(_, _)) = promote(&Elt::Int(1), &Elt::Big(big("9999999999"))) {} else { assert!(false); }
+        if let Ok(Pair::Float(_, _)) = promote(&Elt::Big(big("9999999999")), &Elt::Float(0.5)) {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Type, .. }) = promote(&Elt::Int(1), &Elt::Bool(true)) {} else { assert!(false); }
+    }
+
+    fn ratio(n: i64, d: i64) -> BigRational {
+        return BigRational::new(n.into(), d.into());
+    }
+
+    #[test]
+    fn test_rational_normalises() {
+        if let Elt::Int(2) = rational(ratio(6, 3)) {} else { assert!(false); }
+        if let Elt::Rational(r) = rational(ratio(2, -4)) {
+            assert_eq!((r.numer().to_i32(), r.denom().to_i32()), (Some(-1), Some(2)));
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_promote_rational() {
+        if let Ok(Pair::Rational(_, _)) = promote(&Elt::Rational(ratio(1, 2)), &Elt::Big(big("9999999999"))) {} else { assert!(false); }
+        if let Ok(Pair::Float(_, _)) = promote(&Elt::Rational(ratio(1, 2)), &Elt::Float(0.5)) {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_cmp_exact_float() {
+        let b = BigRational::from_integer(big("9007199254740993")); // 2^53 + 1, which no f64 can hold.
+        assert_eq!(cmp_exact_float(&b, 9007199254740992.0), Ordering::Greater);
+        assert_eq!(cmp_exact_float(&b, 9007199254740994.0), Ordering::Less);
+        assert_eq!(cmp_exact_float(&ratio(-5000000000, 1), -5000000000.0), Ordering::Equal);
+        assert_eq!(cmp_exact_float(&ratio(-5000000000, 1), -4999999999.5), Ordering::Less);
+        assert_eq!(cmp_exact_float(&ratio(1, 3), 1.0 / 3.0), Ordering::Greater);
+        assert_eq!(cmp_exact_float(&b, f64::NAN), Ordering::Less);
+        assert_eq!(cmp_exact_float(&b, f64::NEG_INFINITY), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_div_rem_rational() {
+        assert_eq!(div(Pair::Int(1, 3)).unwrap(), Elt::Rational(ratio(1, 3)));
+        assert_eq!(div(Pair::Int(i32::MIN, -1)).unwrap(), Elt::Big(big("2147483648")));
+        assert_eq!(rem(Pair::Rational(ratio(7, 2), ratio(1, 1))).unwrap(), Elt::Rational(ratio(1, 2)));
+        assert_eq!(rem(Pair::Rational(ratio(-7, 2), ratio(3, 2))).unwrap(), Elt::Rational(ratio(-1, 2)));
+        if let Err(Error { kind: ErrorKind::DivideByZero, .. }) = div(Pair::Rational(ratio(1, 2), ratio(0, 1))) {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_pow_big() {
+        assert_eq!(pow(Pair::Int(2, 100)).unwrap().to_string(), "1267650600228229401496703205376");
+        assert_eq!(pow(Pair::Int(-1, i32::MAX)).unwrap(), Elt::Int(-1));
+        assert_eq!(pow(Pair::Big(big("-1"), big("10000000000"))).unwrap(), Elt::Int(1));
+        assert_eq!(pow(Pair::Big(big("0"), big("10000000000"))).unwrap(), Elt::Int(0));
+        assert_eq!(pow(Pair::Int(2, -3)).unwrap(), Elt::Rational(ratio(1, 8)));
+        assert_eq!(pow(Pair::Rational(ratio(-2, 3), ratio(3, 1))).unwrap(), Elt::Rational(ratio(-8, 27)));
+        assert_eq!(pow(Pair::Rational(ratio(4, 1), ratio(1, 2))).unwrap(), Elt::Float(2.0));
+        if let Err(Error { kind: ErrorKind::Overflow, .. }) = pow(Pair::Int(2, i32::MAX)) {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_promote_complex() {
+        if let Ok(Pair::Complex(y, x)) = promote(&Elt::Rational(ratio(1, 2)), &Elt::Complex(Complex64::new(0.0, 1.0))) {
+            assert_eq!((y, x), (Complex64::new(0.5, 0.0), Complex64::new(0.0, 1.0)));
+        } else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Type, .. }) = promote(&Elt::Complex(Complex64::new(0.0, 1.0)), &Elt::Str("a".to_string())) {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_cmp_complex() {
+        let z = |re: f64, im: f64| Elt::Complex(Complex64::new(re, im));
+        assert_eq!(cmp(&z(1.0, 0.0), &Elt::Int(1)), Some(Ordering::Equal));
+        assert_eq!(cmp(&z(1.0, -1.0), &Elt::Int(1)), Some(Ordering::Less));
+        assert_eq!(cmp(&z(0.0, 5.0), &Elt::Int(1)), Some(Ordering::Less));
+        assert_eq!(cmp(&z(1.0, 1.0), &z(1.0, 2.0)), Some(Ordering::Less));
+        assert_eq!(cmp(&z(1.0, 0.0), &Elt::Bool(true)), None);
+
+        // Exact numbers are not rounded to be compared with a complex one, so
+        // the order stays transitive.
+        let b = Elt::Big(big("9007199254740993")); // 2^53 + 1, which no f64 can hold.
+        let f = Elt::Float(9007199254740992.0);
+        assert_eq!(cmp(&b, &f), Some(Ordering::Greater));
+        assert_eq!(cmp(&b, &z(9007199254740992.0, 0.0)), Some(Ordering::Greater));
+        assert_eq!(cmp(&z(9007199254740992.0, 0.0), &f), Some(Ordering::Equal));
+        assert_eq!(cmp(&z(9007199254740992.0, -1.0), &b), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn test_sqrt() {
+        assert_eq!(sqrt(Elt::Int(4)).unwrap(), Elt::Float(2.0));
+        assert_eq!(sqrt(Elt::Int(-4)).unwrap(), Elt::Complex(Complex64::new(0.0, 2.0)));
+        assert_eq!(sqrt(Elt::Complex(Complex64::new(-4.0, 0.0))).unwrap(), Elt::Complex(Complex64::new(0.0, 2.0)));
+        if let Err(Error { kind: ErrorKind::Type, .. }) = sqrt(Elt::Bool(true)) {} else { assert!(false); }
+    }
+}
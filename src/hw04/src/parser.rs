@@ -1,110 +1,377 @@
-use std::io::{self, BufRead, Write};
+use std::io::Write;
+use std::ops::Range;
+use std::rc::Rc;
 use std::str::FromStr;
 
-use crate::rpn::{Elt, Error, Op};
+use crate::env::Env;
+use crate::infix;
+use crate::lexer::{tokenize, Token};
+use crate::rpn::{Elt, Error, ErrorKind, Op};
+use crate::vm::{Code, Instr, Vm};
 
 use super::rpn::{self, Stack};
 
-/// Start a read-eval-print loop, which runs until an error or `quit`.
-pub fn read_eval_print_loop() -> rpn::Result<()> {
-    // Create a stack to work on.
-    let mut stack = Stack::new();
-
-    loop {
-        // Print a user input prompt.
-        print!("> ");
-        io::stdout().flush().map_err(rpn::Error::IO)?;
-
-        // Read from stdin into a String, and evaluate_line the result.
-        // * An io::Error should be converted into a rpn::Error::IO
-        let mut string = String::new();
-        io::stdin().lock().read_line(&mut string).map_err(rpn::Error::IO)?;
-        evaluate_line(&mut stack, &string)?;
-        println!("{:?}", stack.pop()?);
-    }
-}
-
-fn evaluate_line(stack: &mut Stack, buf: &String) -> rpn::Result<()> {
-    // Create an iterator over the tokens.
-    let tokens = buf.trim().split_whitespace();
-
-    // Evaluate all of the tokens on the line.
-    for s in tokens {
-        if let Ok(i) = i32::from_str(s) {
-            stack.push(Elt::Int(i))?;
-        } else if let Ok(b) = bool::from_str(s) {
-            stack.push(Elt::Bool(b))?;
-        } else {
-            stack.eval(match s {
-                "+" => Op::Add,
-                "~" => Op::Neg,
-                "<->" => Op::Swap,
-                "=" => Op::Eq,
-                "#" => Op::Rand,
-                "quit" => Op::Quit,
-                _ => return Err(Error::Syntax),
-            })?;
+/// Evaluates a line of RPN, writing any output to `out`.
+pub fn evaluate_line(stack: &mut Stack, env: &mut Env, out: &mut dyn Write, buf: &str) -> rpn::Result<()> {
+    return evaluate_tokens(stack, env, out, tokenize(buf)?);
+}
+
+/// Evaluates a line written in infix notation, such as `(3 + 4) * 2`, by
+/// compiling it to RPN first.
+pub fn evaluate_infix(stack: &mut Stack, env: &mut Env, out: &mut dyn Write, buf: &str) -> rpn::Result<()> {
+    return evaluate_tokens(stack, env, out, infix::compile(buf)?);
+}
+
+/// Evaluates a script line by line, stopping at the first error, which is
+/// returned along with the number of its line, counting from 1.
+pub fn evaluate_script(stack: &mut Stack, env: &mut Env, out: &mut dyn Write, script: &str)
+                       -> Result<(), (usize, Error)> {
+    for (i, line) in script.lines().enumerate() {
+        if i == 0 && line.starts_with("#!") {
+            continue;
         }
+        evaluate_line(stack, env, out, line).map_err(|e| (i + 1, e))?;
     }
     return Ok(());
 }
 
+fn evaluate_tokens(stack: &mut Stack, env: &mut Env, out: &mut dyn Write, tokens: Vec<(Token, Range<usize>)>)
+                   -> rpn::Result<()> {
+    // Compile the whole line first, which checks that control structures are
+    // balanced before evaluating any of the tokens.
+    let (tokens, spans): (Vec<Token>, Vec<Range<usize>>) = tokens.into_iter().unzip();
+    let code = compile(tokens, spans)?;
+    return Vm::new(stack, env, out).run(Rc::new(code));
+}
+
+/// A parsed piece of a line: a single token, a word definition, or a control
+/// structure together with the nodes nested inside it.
+enum Node<'a> {
+    Word(&'a str),
+    Str(&'a str),
+    /// `: name body ;`
+    Define(&'a str, &'a [Token]),
+    /// `if then-branch else else-branch then`, where the else is optional.
+    If(Block<'a>, Block<'a>),
+    /// `limit start do body loop`, running body for each index in [start, limit).
+    Do(Block<'a>),
+    /// `begin cond while body repeat`, running body for as long as cond is true.
+    While(Block<'a>, Block<'a>),
+    /// `{ items }`, collecting whatever items pushes into a list.
+    List(Block<'a>),
+    /// `[ body ]`, pushing body as a quotation to be run later.
+    Quote(&'a [Token]),
+}
+
+/// A sequence of nodes, each with the position of the token it starts at.
+type Block<'a> = Vec<(usize, Node<'a>)>;
+
+/// Builds an error about the token at `pos`. The tokens of the line have spans,
+/// but those of word bodies and quotations do not, so their errors are left for
+/// the word that runs them to locate.
+fn error_at(tokens: &[Token], spans: &[Range<usize>], pos: usize, kind: ErrorKind) -> Error {
+    let e = Error::from(kind);
+    return match spans.get(pos) {
+        Some(span) => e.at(&tokens[pos].to_string(), span.clone()),
+        None => e,
+    };
+}
+
+/// Parses a sequence of tokens into nodes. `spans` holds the span of each
+/// token, if they are known.
+fn parse<'a>(tokens: &'a [Token], spans: &[Range<usize>]) -> rpn::Result<Block<'a>> {
+    let mut pos = 0;
+    return match parse_block(tokens, spans, &mut pos, &[])? {
+        (nodes, None) => Ok(nodes),
+        // A stray terminator at the top level has nothing to close.
+        (_, Some(_)) => Err(error_at(tokens, spans, pos - 1, ErrorKind::Unbalanced)),
+    };
+}
+
+/// Parses nodes until one of the `terminators` or the end of the tokens,
+/// returning the nodes and the terminator that was found (if any).
+fn parse_block<'a>(tokens: &'a [Token], spans: &[Range<usize>], pos: &mut usize, terminators: &[&str])
+                   -> rpn::Result<(Block<'a>, Option<&'a str>)> {
+    let mut nodes = vec![];
+    while *pos < tokens.len() {
+        let at = *pos;
+        let s = match &tokens[at] {
+            Token::Word(s) => s.as_str(),
+            Token::Str(s) => {
+                *pos += 1;
+                nodes.push((at, Node::Str(s)));
+                continue;
+            }
+        };
+        *pos += 1;
+
+        if terminators.contains(&s) {
+            return Ok((nodes, Some(s)));
+        }
+        nodes.push((at, match s {
+            ":" => parse_definition(tokens, spans, pos)?,
+            ";" => return Err(error_at(tokens, spans, at, ErrorKind::Syntax)),
+            "if" => {
+                let (then_branch, end) = parse_block(tokens, spans, pos, &["else", "then"])?;
+                let else_branch = match end {
+                    Some("else") => expect_block(tokens, spans, pos, at, "then")?,
+                    Some(_) => vec![],
+                    None => return Err(error_at(tokens, spans, at, ErrorKind::Unbalanced)),
+                };
+                Node::If(then_branch, else_branch)
+            }
+            "do" => Node::Do(expect_block(tokens, spans, pos, at, "loop")?),
+            "begin" => {
+                let cond = expect_block(tokens, spans, pos, at, "while")?;
+                Node::While(cond, expect_block(tokens, spans, pos, at, "repeat")?)
+            }
+            "{" => Node::List(expect_block(tokens, spans, pos, at, "}")?),
+            "[" => {
+                let start = *pos;
+                expect_block(tokens, spans, pos, at, "]")?;
+                Node::Quote(&tokens[start..*pos - 1])
+            }
+            "else" | "then" | "loop" | "while" | "repeat" | "}" | "]" => {
+                return Err(error_at(tokens, spans, at, ErrorKind::Unbalanced));
+            }
+            _ => Node::Word(s),
+        }));
+    }
+    return Ok((nodes, None));
+}
+
+/// Parses nodes up to a required terminator. If the tokens run out first, the
+/// error is reported at the token at `opener`, which was left open.
+fn expect_block<'a>(tokens: &'a [Token], spans: &[Range<usize>], pos: &mut usize, opener: usize, terminator: &str)
+                    -> rpn::Result<Block<'a>> {
+    return match parse_block(tokens, spans, pos, &[terminator])? {
+        (nodes, Some(_)) => Ok(nodes),
+        (_, None) => Err(error_at(tokens, spans, opener, ErrorKind::Unbalanced)),
+    };
+}
+
+/// Parses a word definition `: name body ;`, starting just after the `:`.
+fn parse_definition<'a>(tokens: &'a [Token], spans: &[Range<usize>], pos: &mut usize) -> rpn::Result<Node<'a>> {
+    let colon = *pos - 1;
+    let name = match tokens.get(*pos) {
+        Some(Token::Word(name)) if is_name(name) => name,
+        Some(_) => return Err(error_at(tokens, spans, *pos, ErrorKind::Syntax)),
+        None => return Err(error_at(tokens, spans, colon, ErrorKind::Syntax)),
+    };
+    *pos += 1;
+
+    let start = *pos;
+    while *pos < tokens.len() {
+        *pos += 1;
+        match &tokens[*pos - 1] {
+            Token::Word(s) if s == ";" => {
+                // Reject bodies with unbalanced control structures up front,
+                // rather than when the word is first used.
+                let body = &tokens[start..*pos - 1];
+                parse(body, spans.get(start..*pos - 1).unwrap_or(&[]))?;
+                return Ok(Node::Define(name, body));
+            }
+            Token::Word(s) if s == ":" => return Err(error_at(tokens, spans, *pos - 1, ErrorKind::Syntax)),
+            _ => {}
+        }
+    }
+    // Ran out of tokens without finding the closing `;`.
+    return Err(error_at(tokens, spans, colon, ErrorKind::Syntax));
+}
+
+/// Compiles a sequence of tokens into code for the virtual machine, after
+/// checking that its control structures are balanced. `spans` holds the span
+/// of each token, if they are known.
+pub fn compile(tokens: Vec<Token>, spans: Vec<Range<usize>>) -> rpn::Result<Code> {
+    let mut instrs = vec![];
+    compile_block(&parse(&tokens, &spans)?, &mut instrs);
+    return Ok(Code { tokens, spans, instrs });
+}
+
+/// Compiles nodes onto the end of `instrs`, with control structures turned
+/// into jumps.
+fn compile_block(nodes: &[(usize, Node)], instrs: &mut Vec<(Instr, usize)>) {
+    for (pos, node) in nodes {
+        let pos = *pos;
+        match node {
+            Node::Word(s) => instrs.push((compile_word(s), pos)),
+            Node::Str(s) => instrs.push((Instr::Push(Elt::Str(s.to_string())), pos)),
+            Node::Define(name, body) => {
+                // The body was checked while parsing, so it compiles.
+                let code = compile(body.to_vec(), vec![]).unwrap_or_default();
+                instrs.push((Instr::Define(name.to_string(), Rc::new(code)), pos));
+            }
+            Node::If(then_branch, else_branch) => {
+                let branch = instrs.len();
+                instrs.push((Instr::If(0), pos));
+                compile_block(then_branch, instrs);
+                let jump = instrs.len();
+                instrs.push((Instr::Jump(0), pos));
+                instrs[branch].0 = Instr::If(instrs.len());
+                compile_block(else_branch, instrs);
+                instrs[jump].0 = Instr::Jump(instrs.len());
+            }
+            Node::Do(body) => {
+                let start = instrs.len();
+                instrs.push((Instr::Do(0), pos));
+                compile_block(body, instrs);
+                instrs.push((Instr::Loop(start + 1), pos));
+                instrs[start].0 = Instr::Do(instrs.len());
+            }
+            Node::While(cond, body) => {
+                let start = instrs.len();
+                compile_block(cond, instrs);
+                let branch = instrs.len();
+                instrs.push((Instr::While(0), pos));
+                compile_block(body, instrs);
+                instrs.push((Instr::Jump(start), pos));
+                instrs[branch].0 = Instr::While(instrs.len());
+            }
+            Node::List(items) => {
+                instrs.push((Instr::ListStart, pos));
+                compile_block(items, instrs);
+                instrs.push((Instr::ListEnd, pos));
+            }
+            Node::Quote(body) => {
+                // So was the body of a quotation.
+                let code = compile(body.to_vec(), vec![]).unwrap_or_default();
+                instrs.push((Instr::Quote(Rc::new(code)), pos));
+            }
+        }
+    }
+}
+
+/// Compiles a single word, resolving literals and built-in words. Other names
+/// are left to be resolved when they run, since they may be defined later.
+fn compile_word(s: &str) -> Instr {
+    if let Some(n) = Elt::parse_number(s) {
+        return Instr::Push(n);
+    } else if let Ok(b) = bool::from_str(s) {
+        return Instr::Push(Elt::Bool(b));
+    } else if let Some(name) = s.strip_prefix('\'') {
+        if !is_identifier(name) {
+            return Instr::Fail;
+        }
+        return Instr::Push(Elt::Sym(name.to_string()));
+    }
+    return match s {
+        "store" => Instr::Store,
+        "forget" => Instr::Forget,
+        "words" => Instr::Words,
+        // The index of the innermost loop, or of the one around it.
+        "i" => Instr::Index(1),
+        "j" => Instr::Index(2),
+        "call" => Instr::Call,
+        "map" => Instr::Map,
+        "filter" => Instr::Filter,
+        "fold" => Instr::Fold,
+        "each" => Instr::Each,
+        _ if is_name(s) => Instr::Name(s.to_string()),
+        _ => match Op::from_word(s) {
+            Some(op) => Instr::Op(op),
+            None => Instr::Fail,
+        },
+    };
+}
+
+/// Checks whether a token can name a variable or word that can be used: an
+/// identifier which is not a built-in word.
+pub fn is_name(s: &str) -> bool {
+    return is_identifier(s) && !is_builtin(s);
+}
+
+/// Checks whether a token can name a variable: a letter or underscore,
+/// followed by letters, digits or underscores.
+pub fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    return match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    };
+}
+
+/// Checks whether a token is a built-in word, which cannot be used as a variable
+/// or redefined.
+fn is_builtin(s: &str) -> bool {
+    return Op::from_word(s).is_some() || matches!(s, "call" | "map" | "filter" | "fold" | "each" | "store" | "forget"
+        | "words" | "i" | "j" | "if" | "else" | "then" | "do" | "loop" | "begin" | "while" | "repeat");
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::rpn::{Elt, Error, Stack};
+    use std::io;
+
+    use crate::env::Env;
+    use crate::rpn::{self, Elt, Error, ErrorKind, Stack};
+
+    // The tests have no use for output, so these leave it out.
+    fn evaluate_line(stack: &mut Stack, env: &mut Env, buf: &str) -> rpn::Result<()> {
+        return super::evaluate_line(stack, env, &mut io::sink(), buf);
+    }
 
-    use super::evaluate_line;
+    fn evaluate_infix(stack: &mut Stack, env: &mut Env, buf: &str) -> rpn::Result<()> {
+        return super::evaluate_infix(stack, env, &mut io::sink(), buf);
+    }
+
+    fn evaluate_script(stack: &mut Stack, env: &mut Env, script: &str) -> Result<(), (usize, Error)> {
+        return super::evaluate_script(stack, env, &mut io::sink(), script);
+    }
 
     #[test]
     fn test_evaluate_line_bool() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "true".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
         let s = "false".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
     }
 
     #[test]
     fn test_evaluate_line_int() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "12".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Int(12));
     }
 
     #[test]
     fn test_evaluate_line_plus() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "12".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let s = "13".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let s = "+".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Int(25));
     }
 
     #[test]
     fn test_evaluate_line_neg() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "false".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let s = "~".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
     }
 
     #[test]
     fn test_evaluate_line_swap() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "false".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let s = "15".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let s = "<->".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
         assert_eq!(stack.pop().unwrap(), Elt::Int(15));
     }
@@ -112,22 +379,24 @@ mod tests {
     #[test]
     fn test_evaluate_line_eq() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "12".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let s = "15".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let s = "=".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
     }
 
     #[test]
     fn test_evaluate_line_rand() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "12".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let s = "#".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
         let res = stack.pop();
         assert!(res.is_ok());
         let res = res.unwrap();
@@ -135,21 +404,564 @@ mod tests {
         assert!(res < Elt::Int(12));
     }
 
+    #[test]
+    fn test_evaluate_line_seed() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "7 seed { 0 10 range [ drop 100 # ] map } 7 seed { 0 10 range [ drop 100 # ] map } =".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+    }
+
     #[test]
     fn test_evaluate_line_quit() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "quit".to_string();
-        let res = evaluate_line(&mut stack, &s);
+        let res = evaluate_line(&mut stack, &mut env, &s);
         assert!(res.is_err());
-        if let Err(Error::Quit) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Quit, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
     fn test_evaluate_line_bad_parse() {
         let mut stack = Stack::new();
+        let mut env = Env::new();
         let s = "~false".to_string();
-        let res = evaluate_line(&mut stack, &s);
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_store_recall() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "42 'x store".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert!(stack.pop().is_err());
+        let s = "x x +".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(84));
+    }
+
+    #[test]
+    fn test_evaluate_line_store_overwrite() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "1 'x store true 'x store x".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+    }
+
+    #[test]
+    fn test_evaluate_line_store_bad_name() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "1 2 store".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+
+        let s = "1 '+ store".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_unbound() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "y".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Unbound(name), .. }) = res { assert_eq!(name, "y"); } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_define() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = ": double 2 + ; 3 double".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(5));
+        let s = ": quad double double ;".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        let s = "1 quad".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(5));
+    }
+
+    #[test]
+    fn test_evaluate_line_define_bad() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        for s in &[": double 2 +", ": : ;", ": quit ;", ": 3 ;", ": a : b ; ;", ";"] {
+            let res = evaluate_line(&mut stack, &mut env, s);
+            assert!(res.is_err());
+            if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_line_forget() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = ": one 1 ; 'one forget".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        let s = "one".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Unbound(name), .. }) = res { assert_eq!(name, "one"); } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_name_in_use() {
+        // A variable and a word may not share a name, since the word would
+        // always be run instead of the variable being recalled.
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = ": x 1 ; 42 'x store".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        if let Err(Error { kind: ErrorKind::NameInUse(name), .. }) = res { assert_eq!(name, "x"); } else { assert!(false); }
+        let s = "2 'y store : y 1 ;".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        if let Err(Error { kind: ErrorKind::NameInUse(name), .. }) = res { assert_eq!(name, "y"); } else { assert!(false); }
+        assert!(evaluate_line(&mut stack, &mut env, "y x").is_ok());
+        assert_eq!(stack.values(), [Elt::Int(2), Elt::Int(1)]);
+    }
+
+    #[test]
+    fn test_evaluate_line_recursion() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = ": forever forever ; forever".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Recursion, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_if() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "1 1 = if 10 else 20 then".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(10));
+        let s = "1 2 = if 10 else 20 then".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(20));
+        let s = "5 false if 10 then".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(5));
+    }
+
+    #[test]
+    fn test_evaluate_line_if_type() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "1 if 10 then".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_do() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "0 5 0 do i + loop".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(10));
+        let s = "0 3 0 do 3 0 do j + loop loop".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(9));
+        let s = "7 0 0 do i loop".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(7));
+    }
+
+    #[test]
+    fn test_evaluate_line_while() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "0 'n store begin n 5 = ~ while n 1 + 'n store repeat n".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(5));
+    }
+
+    #[test]
+    fn test_evaluate_line_compare() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "0 'n store begin n 5 < while n 1 + 'n store repeat n".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(5));
+        let s = "3 1 >= 2 2 != or not 1 10 <= xor".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+
+        let s = "1 \"a\" > if 1 then".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        let s = "1 true and".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_radix() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "0x1F 0b1010 band 0o17 bor 1 4 shl bxor -0x10 2 shr bnot".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(3));
+        assert_eq!(stack.pop().unwrap(), Elt::Int(0b11111));
+
+        let s = "0x1G".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+        let s = "1.5 1 shl".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_recursive_word() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = ": countdown 'n store n 0 = if 0 else n n ~ 1 + ~ countdown + then ;".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        let s = "4 countdown".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(10));
+    }
+
+    #[test]
+    fn test_evaluate_line_unbalanced() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        for s in &["true if 1", "true if 1 else 2", "then", "1 0 do", "loop", "begin true while",
+                   "begin 1 repeat", "true if 1 0 do then loop", ": bad if ;", "i"] {
+            let res = evaluate_line(&mut stack, &mut env, s);
+            assert!(res.is_err());
+            if let Err(Error { kind: ErrorKind::Unbalanced, .. }) = res {} else { assert!(false); }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_line_arith() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "7 3 - 4 * 5 / 3 % 2 pow -9 abs max 4 min".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(4));
+    }
+
+    #[test]
+    fn test_evaluate_line_divide_by_zero() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "1 0 /".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::DivideByZero, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_float() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        for (s, f) in &[("1.5", 1.5), ("-.5", -0.5), ("6.02e23", 6.02e23), ("1E-3", 0.001), ("2.", 2.0)] {
+            assert!(evaluate_line(&mut stack, &mut env, s).is_ok());
+            assert_eq!(stack.pop().unwrap(), Elt::Float(*f));
+        }
+        for s in &["1.2.3", "e", "1e", "inf", "nan"] {
+            assert!(evaluate_line(&mut stack, &mut env, s).is_err());
+        }
+    }
+
+    #[test]
+    fn test_evaluate_line_average() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "1 2 + 4 + 3.0 /".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Float(7.0 / 3.0));
+    }
+
+    #[test]
+    fn test_evaluate_line_float_math() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "16 sqrt 2.5 floor 2.5 ceil 1 exp ln 0 cos".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Float(1.0));
+        assert_eq!(stack.pop().unwrap(), Elt::Float(1.0));
+        assert_eq!(stack.pop().unwrap(), Elt::Float(3.0));
+        assert_eq!(stack.pop().unwrap(), Elt::Float(2.0));
+        assert_eq!(stack.pop().unwrap(), Elt::Float(4.0));
+    }
+
+    #[test]
+    fn test_evaluate_line_string() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = r#""hello, " "big \"world\"" concat upper"#.to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Str("HELLO, BIG \"WORLD\"".to_string()));
+    }
+
+    #[test]
+    fn test_evaluate_line_string_conversions() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = r#""1.5" num 2 * str " apples" concat"#.to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Str("3.0 apples".to_string()));
+    }
+
+    #[test]
+    fn test_evaluate_line_string_in_word() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = r#": greet "hello " <-> concat ; "if then" greet"#.to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Str("hello if then".to_string()));
+    }
+
+    #[test]
+    fn test_evaluate_line_unterminated_string() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = r#""hello"#.to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+    }
+
+    fn list(items: &[i32]) -> Elt {
+        return Elt::List(items.iter().map(|i| Elt::Int(*i)).collect());
+    }
+
+    #[test]
+    fn test_evaluate_line_list() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "{ 1 2 1 + { } } { }".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), list(&[]));
+        assert_eq!(stack.pop().unwrap(), Elt::List(vec![Elt::Int(1), Elt::Int(3), list(&[])]));
+
+        let s = "1 { + }".to_string();
+        let res = evaluate_line(&mut stack, &mut env, &s);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_quote() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "[ 1 + ] 'inc store 41 inc call".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(42));
+
+        let s = "2 [ [ 3 * ] call ] call".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(6));
+    }
+
+    #[test]
+    fn test_evaluate_line_map_filter_fold() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "1 6 range [ 2 * ] map".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), list(&[2, 4, 6, 8, 10]));
+
+        let s = "0 10 range [ 3 % 0 = ] filter".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), list(&[0, 3, 6, 9]));
+
+        let s = "1 5 range 1 [ * ] fold".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(24));
+    }
+
+    #[test]
+    fn test_evaluate_line_each() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "{ 1 2 3 } [ 10 * ] each".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(30));
+        assert_eq!(stack.pop().unwrap(), Elt::Int(20));
+        assert_eq!(stack.pop().unwrap(), Elt::Int(10));
+    }
+
+    #[test]
+    fn test_evaluate_line_list_words() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "{ 5 6 } 7 append 'l store l len l 2 nth".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(7));
+        assert_eq!(stack.pop().unwrap(), Elt::Int(3));
+    }
+
+    #[test]
+    fn test_evaluate_line_list_errors() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        for s in &["{ 1", "[ 1", "1 }", "]", "[ 1 if ]"] {
+            let res = evaluate_line(&mut stack, &mut env, s);
+            assert!(res.is_err());
+            if let Err(Error { kind: ErrorKind::Unbalanced, .. }) = res {} else { assert!(false); }
+        }
+        let res = evaluate_line(&mut stack, &mut env, "5 [ 1 + ] map");
         assert!(res.is_err());
-        if let Err(Error::Syntax) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_big() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = ": fact 1 <-> 1 + 1 do i * loop ; 25 fact".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap().to_string(), "15511210043330985984000000");
+
+        let s = "99999999999999999999 1 + 100000000000000000000 =".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+    }
+
+    #[test]
+    fn test_evaluate_line_rational() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "1 3 / 1/6 + 'half store half numer half denom half float".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Float(0.5));
+        assert_eq!(stack.pop().unwrap(), Elt::Int(2));
+        assert_eq!(stack.pop().unwrap(), Elt::Int(1));
+        assert_eq!(env.recall("half").unwrap().to_string(), "1/2");
+    }
+
+    #[test]
+    fn test_evaluate_line_complex() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = "-9 sqrt 1+2i + conj 3 4 cplx abs".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Float(5.0));
+        assert_eq!(stack.pop().unwrap().to_string(), "1.0-5.0i");
+
+        // `i` alone is still the loop index.
+        let s = "0 3 0 do 1i i * + loop".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        assert_eq!(stack.pop().unwrap().to_string(), "0.0+3.0i");
+    }
+
+    /// Evaluates a line that should fail, returning the error's op, token
+    /// and span.
+    fn locate(line: &str) -> (Option<String>, Option<String>, Option<std::ops::Range<usize>>) {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let e = evaluate_line(&mut stack, &mut env, line).unwrap_err();
+        return (e.op().map(String::from), e.token().map(String::from), e.span());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_error_location() {
+        let at = |op: &str, token: &str, span| (Some(op.to_string()), Some(token.to_string()), Some(span));
+        assert_eq!(locate("1 2 + true -\n"), at("-", "-", 11..12));
+        assert_eq!(locate("1 \"a\" +"), at("+", "+", 6..7));
+        assert_eq!(locate("\"é\" x"), at("x", "x", 4..5));
+        // Errors inside of words and quotations are located where they were run.
+        assert_eq!(locate(": f 1 true + ; 2 f"), at("+", "f", 17..18));
+        assert_eq!(locate("{ 1 2 } [ 1 concat ] filter"), at("concat", "filter", 21..27));
+        // Errors inside of control structures are located at the word that failed.
+        assert_eq!(locate("true if 1 0 / then"), at("/", "/", 12..13));
+        assert_eq!(locate("5 if 1 then"), at("if", "if", 2..4));
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let at = |token: &str, span| (None, Some(token.to_string()), Some(span));
+        assert_eq!(locate("1 2 then"), at("then", 4..8));
+        assert_eq!(locate("1 do 2 + loop } 3"), at("}", 14..15));
+        assert_eq!(locate("1 if { 2 then"), at("then", 9..13));
+        assert_eq!(locate("1 if { 2"), at("{", 5..6));
+        assert_eq!(locate(": sq dup"), at(":", 0..1));
+        assert_eq!(locate(": 1 ;"), at("1", 2..3));
+        assert_eq!(locate(": f if ; 1"), at("if", 4..6));
+        assert_eq!(locate("1 \"ab"), at("\"ab", 2..5));
+    }
+
+    #[test]
+    fn test_error_display() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let e = evaluate_line(&mut stack, &mut env, ": f 1 true + ; f").unwrap_err();
+        assert_eq!(e.to_string(), "wrong type in `+` at `f`, column 16");
+        let e = evaluate_line(&mut stack, &mut env, "nope").unwrap_err();
+        assert_eq!(e.to_string(), "unbound name `nope` at `nope`, column 1");
+    }
+
+    #[test]
+    fn test_evaluate_script() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let script = "#!/usr/bin/env hw04\n\\ Squares a number.\n: sq 'x store x x * ;\n\n3 sq \\ 9\n";
+        assert!(evaluate_script(&mut stack, &mut env, script).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(9));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn test_evaluate_script_error_line() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let res = evaluate_script(&mut stack, &mut env, "1 2 +\n\n3 \"a\" *\n4");
+        if let Err((3, Error { kind: ErrorKind::Type, .. })) = res {} else { assert!(false); }
+
+        // A shebang is only allowed on the first line.
+        let res = evaluate_script(&mut stack, &mut env, "1\n#!/bin/sh");
+        if let Err((2, Error { kind: ErrorKind::Syntax, .. })) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_shuffles() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        let s = ": sq dup * ; 3 sq 1 2 over -rot nip tuck depth 4 roll 0 pick".to_string();
+        assert!(evaluate_line(&mut stack, &mut env, &s).is_ok());
+        for x in [9, 9, 4, 2, 1, 2] {
+            assert_eq!(stack.pop().unwrap(), Elt::Int(x));
+        }
+        assert!(stack.is_empty());
+
+        let res = evaluate_line(&mut stack, &mut env, "1 2 3 clear drop");
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_infix() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        assert!(evaluate_line(&mut stack, &mut env, ": sq dup * ; 3 'x store").is_ok());
+        assert!(evaluate_infix(&mut stack, &mut env, "(3 + 4) * 2 - sq(x) / max(1, 3)").is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(11));
+        assert!(evaluate_infix(&mut stack, &mut env, "-x ^ 2 == -9").is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+
+        // Errors are the same as in RPN, and point into the expression.
+        let res = evaluate_infix(&mut stack, &mut env, "1 + 2 / (x - 3)");
+        if let Err(Error { kind: ErrorKind::DivideByZero, .. }) = res {} else { assert!(false); }
+        let e = evaluate_infix(&mut stack, &mut env, "1 + y").unwrap_err();
+        assert_eq!((e.token(), e.span()), (Some("y"), Some(4..5)));
+    }
+}
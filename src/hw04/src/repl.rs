@@ -0,0 +1,366 @@
+use std::io::{BufRead, Write};
+use std::ops::Range;
+
+use crate::infix;
+use crate::input::Input;
+use crate::interpreter::Interpreter;
+use crate::rpn::{self, Error, ErrorKind, Radix};
+use crate::session::Session;
+
+/// Start a read-eval-print loop, which runs until `quit`, the end of the input,
+/// or an error reading or writing. Other errors undo the line they happened on,
+/// and are reported before carrying on. Gives whether every line succeeded, so
+/// that a failure can be noticed when the lines are not typed by a person.
+/// Results are written to the interpreter's output, and messages,
+/// such as where an error happened, to `err`. When the input is typed by a
+/// person, lines can be edited, and are kept as history between sessions.
+/// `history` lists the lines so far, and `history N` runs line N again.
+/// `undo` and `redo` step back and forth between lines.
+/// `save FILE` writes the stack, variables and words to a file, and `load FILE`
+/// replaces them with those saved.
+/// `check LINE` shows the effect that a line would have on the stack, as the
+/// types it takes and leaves, or the error it is certain to hit, without
+/// running it.
+/// `infix` switches between RPN and infix lines, a line starting with `=` is
+/// infix either way, and `rpn` prints the RPN that an infix expression compiles
+/// to.
+/// After each line, the value on top of the stack is shown, or the whole stack
+/// after `show all`, until `show top`. Showing a value leaves it on the stack.
+/// Integers are shown in base 10, or in another base after `hex`, `bin` or
+/// `oct`, until `dec`.
+pub fn read_eval_print_loop<R: BufRead, W: Write, E: Write>(interp: &mut Interpreter<W>, input: &mut Input<R>,
+                                                           err: &mut E) -> rpn::Result<bool> {
+    let mut succeeded = true;
+    let mut infix = false;
+    let mut show = Show::Top;
+    let mut radix = Radix::Dec;
+
+    loop {
+        let mut string = match input.read_line("> ")? {
+            Some(line) => line,
+            None => return Ok(succeeded),
+        };
+
+        // `history` lists the lines read so far, and `history N` runs line N
+        // again, as if it had been typed.
+        if string.trim() == "history" {
+            for (i, line) in input.history().iter().enumerate() {
+                writeln!(interp.output_mut(), "{:5}  {}", i + 1, line).map_err(ErrorKind::IO)?;
+            }
+            continue;
+        }
+        if let Some(n) = command(&string, "history") {
+            let n: usize = n.trim().parse().unwrap_or(0);
+            match input.history().get(n.wrapping_sub(1)) {
+                Some(line) => string = line.clone(),
+                None => {
+                    writeln!(err, "No line {} in history", n).map_err(ErrorKind::IO)?;
+                    succeeded = false;
+                    continue;
+                }
+            }
+            writeln!(interp.output_mut(), "{}", string).map_err(ErrorKind::IO)?;
+        }
+        input.remember(&string);
+
+        // Undo and redo step between whole lines, so they are commands on a
+        // line of their own rather than words.
+        match string.trim() {
+            "undo" => {
+                if interp.stack_mut().undo() {
+                    display(interp, show, radix)?;
+                } else {
+                    writeln!(err, "Nothing to undo").map_err(ErrorKind::IO)?;
+                }
+                continue;
+            }
+            "redo" => {
+                if interp.stack_mut().redo() {
+                    display(interp, show, radix)?;
+                } else {
+                    writeln!(err, "Nothing to redo").map_err(ErrorKind::IO)?;
+                }
+                continue;
+            }
+            "infix" => {
+                infix = !infix;
+                writeln!(err, "Infix mode {}", if infix { "on" } else { "off" }).map_err(ErrorKind::IO)?;
+                continue;
+            }
+            "show top" => {
+                show = Show::Top;
+                continue;
+            }
+            "show all" => {
+                show = Show::All;
+                continue;
+            }
+            "hex" | "dec" | "bin" | "oct" => {
+                radix = match string.trim() {
+                    "hex" => Radix::Hex,
+                    "bin" => Radix::Bin,
+                    "oct" => Radix::Oct,
+                    _ => Radix::Dec,
+                };
+                continue;
+            }
+            _ => {}
+        }
+
+        // `save` and `load` take a file name, which is everything after them.
+        // A file that cannot be used is reported like any other mistake.
+        if let Some(path) = command(&string, "save") {
+            if let Err(e) = interp.session().save(path.trim()) {
+                recover(err, &string, &e)?;
+                succeeded = false;
+            }
+            continue;
+        }
+        if let Some(path) = command(&string, "load") {
+            match Session::load(path.trim()).and_then(|s| interp.restore(s)) {
+                Ok(()) => display(interp, show, radix)?,
+                Err(e) => {
+                    recover(err, &string, &e)?;
+                    succeeded = false;
+                }
+            }
+            continue;
+        }
+
+        // `check` works out what a line would do to the stack, without running
+        // it, along with what each word it defines would do. The command word
+        // is blanked out, as below, so that errors point at the right columns.
+        if let Some(line) = command(&string, "check") {
+            match interp.check(&line) {
+                Ok(summary) => {
+                    for (name, effect) in &summary.words {
+                        writeln!(interp.output_mut(), "{} {}", name, effect).map_err(ErrorKind::IO)?;
+                    }
+                    writeln!(interp.output_mut(), "{}", summary.effect).map_err(ErrorKind::IO)?;
+                }
+                Err(e) => recover(err, &string, &e)?,
+            }
+            continue;
+        }
+
+        // Blank out the command word rather than removing it, so that errors
+        // still point at the right columns.
+        let res = if let Some(expr) = command(&string, "rpn") {
+            match infix::compile(&expr) {
+                Ok(tokens) => {
+                    writeln!(interp.output_mut(), "{}", infix::to_rpn(&tokens)).map_err(ErrorKind::IO)?;
+                    continue;
+                }
+                Err(e) => Err(e),
+            }
+        } else if let Some(expr) = command(&string, "=") {
+            interp.eval_infix(&expr)
+        } else if infix {
+            interp.eval_infix(&string)
+        } else {
+            interp.eval_str(&string)
+        };
+        // Each line happens entirely or not at all: one that fails is undone,
+        // so that it can be fixed and tried again. Only quitting, or failing
+        // to read or write, ends the session.
+        if let Err(e) = res {
+            interp.rollback();
+            if let ErrorKind::Quit | ErrorKind::IO(_) = e.kind {
+                return Err(e);
+            }
+            recover(err, &string, &e)?;
+            succeeded = false;
+            continue;
+        }
+        interp.checkpoint();
+        display(interp, show, radix)?;
+    }
+}
+
+/// How much of the stack the read-eval-print loop shows after each line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Show {
+    /// Only the value on top, if there is one.
+    Top,
+    /// Every value, one per line, from the bottom up.
+    All,
+}
+
+/// Writes the values on the stack that `show` asks for to the interpreter's
+/// output, with integers in base `radix`, without changing the stack.
+fn display<W: Write>(interp: &mut Interpreter<W>, show: Show, radix: Radix) -> rpn::Result<()> {
+    let values = match show {
+        Show::Top => interp.stack().values().last().cloned().into_iter().collect(),
+        Show::All => interp.stack().values().to_vec(),
+    };
+    for e in values {
+        writeln!(interp.output_mut(), "{}", radix.format(&e)).map_err(ErrorKind::IO)?;
+    }
+    return Ok(());
+}
+
+/// Runs a whole script, or a single expression, then writes the value left on
+/// top of the stack, if any, to the interpreter's output. A first line starting
+/// with `#!` is skipped, so that scripts can be made executable. Where an error
+/// happened is written to `err`.
+pub fn run_script<W: Write, E: Write>(interp: &mut Interpreter<W>, script: &str, err: &mut E) -> rpn::Result<()> {
+    if let Err((n, e)) = interp.eval_script(script) {
+        // A single expression needs no line number.
+        if !matches!(e.kind, ErrorKind::Quit) && script.lines().nth(1).is_some() {
+            writeln!(err, "line {}:", n).map_err(ErrorKind::IO)?;
+        }
+        report(err, script.lines().nth(n - 1).unwrap_or(""), &e)?;
+        return Err(e);
+    }
+    if let Ok(top) = interp.stack_mut().pop() {
+        writeln!(interp.output_mut(), "{}", top).map_err(ErrorKind::IO)?;
+    }
+    return Ok(());
+}
+
+/// Checks whether a line starts with a command word, giving the rest of the
+/// line with the word blanked out if it does.
+fn command(line: &str, word: &str) -> Option<String> {
+    let rest = line.trim_start().strip_prefix(word)?;
+    if rest.trim().is_empty() || !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    return Some(" ".repeat(line.len() - rest.len()) + rest);
+}
+
+/// Points out the token of a line that caused an error, if it is known.
+fn report<E: Write>(err: &mut E, line: &str, e: &Error) -> rpn::Result<()> {
+    // Quitting is not a mistake.
+    if matches!(e.kind, ErrorKind::Quit) {
+        return Ok(());
+    }
+    if let Some(span) = e.span() {
+        writeln!(err, "{}", line.trim_end()).map_err(ErrorKind::IO)?;
+        writeln!(err, "{}", underline(&span)).map_err(ErrorKind::IO)?;
+    }
+    return Ok(());
+}
+
+/// Reports an error that the read-eval-print loop carries on from, saying what
+/// it was as well as where.
+fn recover<E: Write>(err: &mut E, line: &str, e: &Error) -> rpn::Result<()> {
+    report(err, line, e)?;
+    writeln!(err, "Error: {}", e).map_err(ErrorKind::IO)?;
+    return Ok(());
+}
+
+/// Draws carets under a span of columns.
+fn underline(span: &Range<usize>) -> String {
+    return " ".repeat(span.start) + &"^".repeat(span.len().max(1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the REPL over some input, giving its result, output and messages.
+    fn repl(lines: &str) -> (rpn::Result<bool>, String, String) {
+        let mut interp = Interpreter::with_output(vec![]);
+        let mut input = Input::new(lines.as_bytes(), false);
+        let mut err = vec![];
+        let res = read_eval_print_loop(&mut interp, &mut input, &mut err);
+        let out = String::from_utf8(interp.output().clone()).unwrap();
+        return (res, out, String::from_utf8(err).unwrap());
+    }
+
+    #[test]
+    fn test_repl() {
+        let (res, out, err) = repl("1 2 +\n: sq dup * ; 4 sq\n= sq(2) + 1\nrpn 1 + 2\nhistory 2\nredo\n");
+        assert_eq!(res.ok(), Some(true));
+        assert_eq!(out, "3\n16\n5\n1 2 +\n: sq dup * ; 4 sq\n16\n");
+        assert_eq!(err, "Nothing to redo\n");
+    }
+
+    #[test]
+    fn test_repl_show() {
+        // Lines which leave nothing new, even blank ones, show the same top.
+        let (res, out, _) = repl(": nop ;\n1\n\n\"a\" true\nshow all\n1/2 { 2 }\nshow top\ndrop\nclear\nundo\n");
+        assert_eq!(res.ok(), Some(true));
+        assert_eq!(out, "1\n1\ntrue\n1\na\ntrue\n1/2\n{ 2 }\n1/2\n1/2\n");
+    }
+
+    #[test]
+    fn test_repl_radix() {
+        let (res, out, _) = repl("0x1F\nhex\n0b1010 band\n{ -1 2.5 }\nbin\ndrop 0o17 4 shl\noct\n8\ndec\n");
+        assert_eq!(res.ok(), Some(true));
+        assert_eq!(out, "31\n0xA\n{ -0x1 2.5 }\n0b11110000\n0o10\n");
+    }
+
+    #[test]
+    fn test_repl_error() {
+        // A failed line is undone, and the session carries on, though it did
+        // not all succeed.
+        let (res, out, err) = repl("1 2\n3 * \"a\" +\nshow all\n4\nload /nonexistent/session.json\n5\n");
+        assert_eq!(res.ok(), Some(false));
+        assert_eq!(out, "2\n1\n2\n4\n1\n2\n4\n5\n");
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(lines[..3], ["3 * \"a\" +", "        ^", "Error: wrong type at `+`, column 9"]);
+        assert!(lines[3].starts_with("Error: IO error"));
+
+        // So are the variables and words it changed.
+        let (res, out, err) = repl("5 'x store 1 0 /\n: f 1 ; 1 0 /\nx\nf\n");
+        assert_eq!(res.ok(), Some(false));
+        assert_eq!(out, "");
+        let errors: Vec<&str> = err.lines().filter(|line| line.starts_with("Error")).collect();
+        assert_eq!(errors[2..], ["Error: unbound name `x` at `x`, column 1", "Error: unbound name `f` at `f`, column 1"]);
+
+        let (res, out, _) = repl("1 +\n2\n");
+        assert_eq!(res.ok(), Some(false));
+        assert_eq!(out, "2\n");
+
+        let (res, out, err) = repl("1\n2 quit 3\n4\n");
+        if let Err(Error { kind: ErrorKind::Quit, .. }) = res {} else { assert!(false); }
+        assert_eq!((out.as_str(), err.as_str()), ("1\n", ""));
+    }
+
+    #[test]
+    fn test_repl_check() {
+        // Finding that a line would fail is not a failure.
+        let (res, out, err) = repl("\"a\"\ncheck : sq dup * ; 2 sq\ncheck 1 +\ncheck sq\n");
+        assert_eq!(res.ok(), Some(true));
+        assert_eq!(out, "a\nsq ( num -- num )\n( -- num )\n");
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(lines, ["check 1 +", "        ^", "Error: wrong type at `+`, column 9",
+                           "check sq", "      ^^", "Error: unbound name `sq` at `sq`, column 7"]);
+    }
+
+    #[test]
+    fn test_run_script() {
+        let mut interp = Interpreter::with_output(vec![]);
+        let mut err = vec![];
+        assert!(run_script(&mut interp, "1 2\n+", &mut err).is_ok());
+        assert_eq!(interp.output(), b"3\n");
+
+        let res = run_script(&mut interp, "1\n\n1 0 /", &mut err);
+        if let Err(Error { kind: ErrorKind::DivideByZero, .. }) = res {} else { assert!(false); }
+        assert_eq!(String::from_utf8(err).unwrap(), "line 3:\n1 0 /\n    ^\n");
+
+        // Errors that are certain to happen are found before anything runs.
+        let mut interp = Interpreter::with_output(vec![]);
+        let mut err = vec![];
+        let res = run_script(&mut interp, "1 2\n\"a\" words\n- -", &mut err);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        assert!(interp.stack().is_empty());
+        assert_eq!(String::from_utf8(err).unwrap(), "line 3:\n- -\n^\n");
+    }
+
+    #[test]
+    fn test_underline() {
+        assert_eq!(underline(&(2..5)), "  ^^^");
+        assert_eq!(underline(&(0..0)), "^");
+    }
+
+    #[test]
+    fn test_command() {
+        assert_eq!(command("= 1 + 2\n", "="), Some("  1 + 2\n".to_string()));
+        assert_eq!(command("  rpn (1)", "rpn"), Some("      (1)".to_string()));
+        assert_eq!(command("==", "="), None);
+        assert_eq!(command("=\n", "="), None);
+        assert_eq!(command("rpnx 1", "rpn"), None);
+    }
+}
@@ -1,50 +1,620 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt;
 use std::io;
+use std::ops::Range;
+use std::rc::Rc;
 use std::result;
+use std::str::FromStr;
+use num_bigint::{BigInt, RandBigInt};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{Num, Signed, Zero};
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Eq, PartialEq, Ord, PartialOrd, Debug)]
-/// An element of the stack. May be either integer or boolean.
+use crate::num::{self, Pair};
+use crate::vm::Code;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// An element of the stack. May be a number, a boolean, a string, a quoted
+/// name, a list or a quotation.
 pub enum Elt {
     Int(i32),
+    /// An integer too large for Int. Integers that fit in an Int are never Big.
+    Big(BigInt),
+    /// An exact fraction, in lowest terms. Its denominator is never 1.
+    Rational(BigRational),
+    #[serde(serialize_with = "crate::session::serialize_float", deserialize_with = "crate::session::deserialize_float")]
+    Float(f64),
+    /// A complex number with float parts, written `1+2i` or `2i`.
+    #[serde(serialize_with = "crate::session::serialize_complex", deserialize_with = "crate::session::deserialize_complex")]
+    Complex(Complex64),
     Bool(bool),
+    Str(String),
+    /// A quoted name (e.g. `'x`), used to refer to a variable without recalling it.
+    Sym(String),
+    List(Vec<Elt>),
+    /// A block of code (e.g. `[ 1 + ]`), run by words such as `call` and `map`.
+    /// It is compiled once, and keeps its tokens for printing and saving.
+    #[serde(serialize_with = "crate::session::serialize_quote", deserialize_with = "crate::session::deserialize_quote")]
+    Quote(Rc<Code>),
+}
+
+impl Elt {
+    /// Parses a number literal: an integer of any size such as `12`, or `0x1F`,
+    /// `0b1010` or `0o17` in another base, a fraction
+    /// such as `-1/3`, a float such as `1.5`, `-.5` or `6.02e23`, or a complex
+    /// number such as `2i` or `1.5-2i`. Only digits, signs, slashes, points and
+    /// exponents are accepted, so that names like `inf` and `nan` stay available
+    /// for variables, and `i` stays available for loops.
+    pub fn parse_number(s: &str) -> Option<Elt> {
+        let digits = s.strip_prefix('-').unwrap_or(s);
+        if let Some(radix) = Radix::of_prefix(digits) {
+            return Elt::parse_radix(s, radix);
+        }
+        if let Ok(i) = i32::from_str(s) {
+            return Some(Elt::Int(i));
+        }
+        if let Ok(b) = BigInt::from_str(s) {
+            return Some(num::int(b));
+        }
+        if let Some(s) = s.strip_suffix('i') {
+            return Elt::parse_complex(s);
+        }
+        if let Some((n, d)) = s.split_once('/') {
+            // Only the numerator may have a sign.
+            let d = match d.chars().next() {
+                Some(c) if c.is_ascii_digit() => BigInt::from_str(d).ok()?,
+                _ => return None,
+            };
+            if d.is_zero() {
+                return None;
+            }
+            return Some(num::rational(BigRational::new(BigInt::from_str(n).ok()?, d)));
+        }
+        if !s.chars().any(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        if !s.chars().all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E')) {
+            return None;
+        }
+        return f64::from_str(s).ok().map(Elt::Float);
+    }
+
+    /// Parses an integer literal written in a base other than 10, with the
+    /// prefix of that base, and optionally negated.
+    fn parse_radix(s: &str, radix: Radix) -> Option<Elt> {
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        // The digits may not have a sign of their own.
+        let digits = &s[2..];
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix.base())) {
+            return None;
+        }
+        let n = BigInt::from_str_radix(digits, radix.base()).ok()?;
+        return Some(num::int(if negative { -n } else { n }));
+    }
+
+    /// Parses a complex literal, without its trailing `i`. The imaginary part
+    /// starts at the last sign that is not part of an exponent.
+    fn parse_complex(s: &str) -> Option<Elt> {
+        let split = s.char_indices().rev()
+            .find(|&(i, c)| i > 0 && matches!(c, '+' | '-') && !s[..i].ends_with(['e', 'E']))
+            .map_or(0, |(i, _)| i);
+        let im = num::to_float(&Elt::parse_number(&s[split..])?)?;
+        let re = match split {
+            0 => 0.0,
+            _ => num::to_float(&Elt::parse_number(&s[..split])?)?,
+        };
+        return Some(Elt::Complex(Complex64::new(re, im)));
+    }
+
+    /// Orders the kinds of element: numbers, then booleans, strings, names,
+    /// lists and quotations.
+    fn rank(&self) -> u8 {
+        return match self {
+            Elt::Int(_) | Elt::Big(_) | Elt::Rational(_) | Elt::Float(_) | Elt::Complex(_) => 0,
+            Elt::Bool(_) => 1,
+            Elt::Str(_) => 2,
+            Elt::Sym(_) => 3,
+            Elt::List(_) => 4,
+            Elt::Quote(_) => 5,
+        };
+    }
+}
+
+// Elements print the way a person would write them. Floats always show a point
+// or an exponent, so they can be told apart from integers, and strings inside of
+// lists are quoted, so they can be told apart from the other items.
+impl fmt::Display for Elt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Elt::Int(i) => write!(f, "{}", i),
+            Elt::Big(b) => write!(f, "{}", b),
+            Elt::Rational(r) => write!(f, "{}", r),
+            Elt::Float(x) => write!(f, "{:?}", x),
+            Elt::Complex(z) if z.im.is_sign_negative() => write!(f, "{:?}{:?}i", z.re, z.im),
+            Elt::Complex(z) => write!(f, "{:?}+{:?}i", z.re, z.im),
+            Elt::Bool(b) => write!(f, "{}", b),
+            Elt::Str(s) => write!(f, "{}", s),
+            Elt::Sym(name) => write!(f, "'{}", name),
+            Elt::List(list) => {
+                write!(f, "{{")?;
+                for e in list {
+                    match e {
+                        Elt::Str(s) => write!(f, " {:?}", s)?,
+                        e => write!(f, " {}", e)?,
+                    }
+                }
+                write!(f, " }}")
+            }
+            Elt::Quote(body) => {
+                write!(f, "[")?;
+                for token in &body.tokens {
+                    write!(f, " {}", token)?;
+                }
+                write!(f, " ]")
+            }
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A base that integers can be written in.
+pub enum Radix {
+    Bin,
+    Oct,
+    Dec,
+    Hex,
+}
+
+impl Radix {
+    /// The number of digits in the base.
+    pub fn base(self) -> u32 {
+        return match self {
+            Radix::Bin => 2,
+            Radix::Oct => 8,
+            Radix::Dec => 10,
+            Radix::Hex => 16,
+        };
+    }
+
+    /// Finds the base that a literal is written in from its prefix, such as
+    /// `0x`, if it has one.
+    fn of_prefix(s: &str) -> Option<Radix> {
+        return match s.get(..2)? {
+            "0b" | "0B" => Some(Radix::Bin),
+            "0o" | "0O" => Some(Radix::Oct),
+            "0x" | "0X" => Some(Radix::Hex),
+            _ => None,
+        };
+    }
+
+    /// Writes a value as Display would, but with integers, including those in
+    /// lists, written in this base, with the prefix that lets them be read back
+    /// in. Other numbers are always written in base 10.
+    pub fn format(self, e: &Elt) -> String {
+        let (negative, magnitude) = match e {
+            Elt::Int(i) => (*i < 0, BigInt::from(*i).magnitude().clone()),
+            Elt::Big(b) => (b.is_negative(), b.magnitude().clone()),
+            Elt::List(list) => {
+                let items: Vec<String> = list.iter().map(|e| match e {
+                    Elt::Str(s) => format!(" {:?}", s),
+                    e => format!(" {}", self.format(e)),
+                }).collect();
+                return format!("{{{} }}", items.concat());
+            }
+            e => return e.to_string(),
+        };
+        let digits = match self {
+            Radix::Bin => format!("0b{:b}", magnitude),
+            Radix::Oct => format!("0o{:o}", magnitude),
+            Radix::Dec => return e.to_string(),
+            Radix::Hex => format!("0x{:X}", magnitude),
+        };
+        return if negative { format!("-{}", digits) } else { digits };
+    }
+}
+
+// Numbers compare by value regardless of how they are represented, so 1 = 1.0.
+// See num::cmp for how floats are given a total order.
+impl Ord for Elt {
+    fn cmp(&self, other: &Elt) -> Ordering {
+        if let Some(o) = num::cmp(self, other) {
+            return o;
+        }
+        return match (self, other) {
+            (Elt::Bool(x), Elt::Bool(y)) => x.cmp(y),
+            (Elt::Str(x), Elt::Str(y)) => x.cmp(y),
+            (Elt::Sym(x), Elt::Sym(y)) => x.cmp(y),
+            (Elt::List(x), Elt::List(y)) => x.cmp(y),
+            (Elt::Quote(x), Elt::Quote(y)) => x.tokens.cmp(&y.tokens),
+            (x, y) => x.rank().cmp(&y.rank()),
+        };
+    }
+}
+
+impl PartialOrd for Elt {
+    fn partial_cmp(&self, other: &Elt) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl PartialEq for Elt {
+    fn eq(&self, other: &Elt) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
 }
 
+impl Eq for Elt {}
+
 #[derive(Debug)]
-/// An RPN calculator error.
-pub enum Error {
+/// The kinds of RPN calculator error.
+pub enum ErrorKind {
     /// Tried to pop from an empty stack.
     Underflow,
     /// Tried to operate on invalid types (e.g. 4 + true)
     Type,
     /// Unable to parse the input.
     Syntax,
+    /// A control word (e.g. `then`) is missing its partner, or is used outside
+    /// of its structure.
+    Unbalanced,
+    /// Tried to recall a variable or forget a word that does not exist.
+    Unbound(String),
+    /// Tried to store a variable under the name of a word, or define a word
+    /// under the name of a variable, which would hide one of them.
+    NameInUse(String),
+    /// User-defined words called each other too deeply.
+    Recursion,
+    /// The result of an arithmetic operation is too large to compute, or has
+    /// no integer value (e.g. converting infinity to an integer).
+    Overflow,
+    /// Tried to divide (or take the remainder) by zero.
+    DivideByZero,
+    /// Tried to take an item from past the end of a list.
+    Index,
+    /// An argument is outside of the values an operation accepts (e.g. a
+    /// random bound that is not positive).
+    Domain,
     /// Some IO error occurred.
     IO(io::Error),
     /// The user quit the program (with `quit`).
     Quit,
 }
 
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            ErrorKind::Underflow => write!(f, "stack underflow"),
+            ErrorKind::Type => write!(f, "wrong type"),
+            ErrorKind::Syntax => write!(f, "syntax error"),
+            ErrorKind::Unbalanced => write!(f, "unbalanced control structure"),
+            ErrorKind::Unbound(name) => write!(f, "unbound name `{}`", name),
+            ErrorKind::NameInUse(name) => write!(f, "name `{}` is already in use", name),
+            ErrorKind::Recursion => write!(f, "recursion too deep"),
+            ErrorKind::Overflow => write!(f, "overflow"),
+            ErrorKind::DivideByZero => write!(f, "division by zero"),
+            ErrorKind::Index => write!(f, "index out of range"),
+            ErrorKind::Domain => write!(f, "argument out of range"),
+            ErrorKind::IO(e) => write!(f, "IO error: {}", e),
+            ErrorKind::Quit => write!(f, "quit"),
+        };
+    }
+}
+
 #[derive(Debug)]
+/// An RPN calculator error, along with where it happened, as far as is known.
+pub struct Error {
+    pub kind: ErrorKind,
+    /// Boxed, so that results stay small on deeply recursive paths.
+    context: Option<Box<Context>>,
+}
+
+#[derive(Debug, Default)]
+struct Context {
+    op: Option<String>,
+    token: Option<(String, Range<usize>)>,
+}
+
+impl Error {
+    /// The operation being attempted, such as `+` or `store`.
+    pub fn op(&self) -> Option<&str> {
+        return self.context.as_ref()?.op.as_deref();
+    }
+
+    /// The token on the line that failed.
+    pub fn token(&self) -> Option<&str> {
+        return self.context.as_ref()?.token.as_ref().map(|(token, _)| token.as_str());
+    }
+
+    /// The columns of the line that the failing token spans, counting
+    /// characters from 0.
+    pub fn span(&self) -> Option<Range<usize>> {
+        return self.context.as_ref()?.token.as_ref().map(|(_, span)| span.clone());
+    }
+
+    /// Records the operation being attempted, unless a more specific one
+    /// already was.
+    pub fn during(mut self, op: &str) -> Error {
+        self.context.get_or_insert_with(Box::default).op.get_or_insert_with(|| op.to_string());
+        return self;
+    }
+
+    /// Records the token that failed and its span, unless a more specific
+    /// one already was.
+    pub fn at(mut self, token: &str, span: Range<usize>) -> Error {
+        self.context.get_or_insert_with(Box::default).token.get_or_insert_with(|| (token.to_string(), span));
+        return self;
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        return Error { kind, context: None };
+    }
+}
+
+// Errors read as a sentence, e.g. "wrong type in `+` at `x`, column 3". The op
+// is left out when it is the failing token itself.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(op) = self.op() {
+            if self.token() != Some(op) {
+                write!(f, " in `{}`", op)?;
+            }
+        }
+        if let (Some(token), Some(span)) = (self.token(), self.span()) {
+            write!(f, " at `{}`, column {}", token, span.start + 1)?;
+        }
+        return Ok(());
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match &self.kind {
+            ErrorKind::IO(e) => Some(e),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 /// Types of RPN calculator operations.
 pub enum Op {
     /// Adds two numbers: pop x, pop y, push x + y.
     Add,
+    /// Subtracts two numbers: pop x, pop y, push y - x.
+    Sub,
+    /// Multiplies two numbers: pop x, pop y, push x * y.
+    Mul,
+    /// Divides two numbers exactly, unless either is a float: pop x, pop y,
+    /// push y / x.
+    Div,
+    /// Takes the remainder of a division: pop x, pop y, push y % x.
+    Rem,
+    /// Raises a number to a power: pop x, pop y, push y ^ x.
+    Pow,
+    /// Takes the absolute value of a number: pop x, push |x|.
+    Abs,
+    /// Takes the smaller of two numbers: pop x, pop y, push min(x, y).
+    Min,
+    /// Takes the larger of two numbers: pop x, pop y, push max(x, y).
+    Max,
+    /// Takes the square root of a number: pop x, push sqrt(x). Negative
+    /// numbers have complex roots.
+    Sqrt,
+    /// Takes the sine of an angle in radians: pop x, push sin(x).
+    Sin,
+    /// Takes the cosine of an angle in radians: pop x, push cos(x).
+    Cos,
+    /// Takes the tangent of an angle in radians: pop x, push tan(x).
+    Tan,
+    /// Takes the inverse sine of a number: pop x, push asin(x).
+    Asin,
+    /// Takes the inverse cosine of a number: pop x, push acos(x).
+    Acos,
+    /// Takes the inverse tangent of a number: pop x, push atan(x).
+    Atan,
+    /// Raises e to a power: pop x, push e ^ x.
+    Exp,
+    /// Takes the natural logarithm of a number: pop x, push ln(x).
+    Ln,
+    /// Takes the base 10 logarithm of a number: pop x, push log10(x).
+    Log,
+    /// Rounds a number down: pop x, push floor(x).
+    Floor,
+    /// Rounds a number up: pop x, push ceil(x).
+    Ceil,
+    /// Rounds a number to the nearest integer, with halves away from zero: pop x, push round(x).
+    Round,
+    /// Rounds a number towards zero: pop x, push trunc(x).
+    Trunc,
+    /// Converts a number to an integer, rounding towards zero: pop x, push int(x).
+    ToInt,
+    /// Takes the numerator of a fraction in lowest terms: pop x, push numer(x).
+    Numer,
+    /// Takes the denominator of a fraction in lowest terms: pop x, push denom(x).
+    Denom,
+    /// Converts a number to a float: pop x, push float(x).
+    ToFloat,
+    /// Builds a complex number from its parts: pop x, pop y, push y + xi.
+    Cplx,
+    /// Takes the real part of a number: pop x, push re(x).
+    Re,
+    /// Takes the imaginary part of a number: pop x, push im(x).
+    Im,
+    /// Takes the complex conjugate of a number: pop x, push conj(x).
+    Conj,
+    /// Takes the angle of a number from the positive real axis, in radians:
+    /// pop x, push arg(x).
+    Arg,
+    /// Converts a number to polar form: pop x, push |x|, push arg(x).
+    Polar,
+    /// Converts polar form to a complex number: pop t, pop r, push r * e ^ ti.
+    Rect,
+    /// Joins two strings: pop x, pop y, push y followed by x.
+    Concat,
+    /// Counts the characters in a string or the items in a list: pop x, push len(x).
+    Len,
+    /// Takes part of a string: pop n, pop i, pop x, push the n characters of x
+    /// starting at character i. Both are clamped to the bounds of x.
+    Substr,
+    /// Converts a string to upper case: pop x, push upper(x).
+    Upper,
+    /// Converts a string to lower case: pop x, push lower(x).
+    Lower,
+    /// Converts any value to a string: pop x, push str(x).
+    ToStr,
+    /// Parses a string as a number: pop x, push num(x).
+    ToNum,
+    /// Lists a range of integers: pop x, pop y, push the list of y up to but
+    /// not including x.
+    Range,
+    /// Takes an item from a list, counting from 0: pop i, pop x, push x[i].
+    Nth,
+    /// Adds an item to the end of a list: pop e, pop x, push x with e appended.
+    Append,
     /// Checks equality of two values: pop x, pop y, push x == y.
     Eq,
+    /// Checks inequality of two values: pop x, pop y, push x != y.
+    Ne,
+    /// Compares two real numbers, or two strings: pop x, pop y, push y < x.
+    Lt,
+    /// Compares two real numbers, or two strings: pop x, pop y, push y <= x.
+    Le,
+    /// Compares two real numbers, or two strings: pop x, pop y, push y > x.
+    Gt,
+    /// Compares two real numbers, or two strings: pop x, pop y, push y >= x.
+    Ge,
+    /// Checks that two booleans are both true: pop x, pop y, push y and x.
+    And,
+    /// Checks that either of two booleans is true: pop x, pop y, push y or x.
+    Or,
+    /// Checks that exactly one of two booleans is true: pop x, pop y, push y xor x.
+    Xor,
+    /// Negates a boolean: pop x, push not x.
+    Not,
+    /// Combines the bits of two integers: pop x, pop y, push y & x. Negative
+    /// integers act as if in two's complement, with as many bits as needed.
+    BAnd,
+    /// Combines the bits of two integers: pop x, pop y, push y | x.
+    BOr,
+    /// Combines the bits of two integers: pop x, pop y, push y ^ x.
+    BXor,
+    /// Flips the bits of an integer: pop x, push !x, which is -x - 1.
+    BNot,
+    /// Shifts an integer left: pop n, pop x, push x << n.
+    Shl,
+    /// Shifts an integer right, rounding down: pop n, pop x, push x >> n.
+    Shr,
     /// Negates a value: pop x, push ~x.
     Neg,
     /// Swaps two values: pop x, pop y, push x, push y.
     Swap,
-    /// Computes a random number: pop x, push random number in [0, x).
+    /// Copies the top value: pop x, push x, push x.
+    Dup,
+    /// Discards the top value: pop x.
+    Drop,
+    /// Copies the second value to the top: pop x, pop y, push y, push x, push y.
+    Over,
+    /// Moves the third value to the top: pop x, pop y, pop z, push y, push x, push z.
+    Rot,
+    /// Moves the top value to third: pop x, pop y, pop z, push x, push z, push y.
+    RotBack,
+    /// Discards the second value: pop x, pop y, push x.
+    Nip,
+    /// Copies the top value below the second: pop x, pop y, push x, push y, push x.
+    Tuck,
+    /// Copies the nth value, counting from 0 at the top: pop n, push the nth value.
+    Pick,
+    /// Moves the nth value, counting from 0 at the top, to the top: pop n, then
+    /// remove the nth value and push it.
+    Roll,
+    /// Discards every value on the stack.
+    Clear,
+    /// Counts the values on the stack: push the count.
+    Depth,
+    /// Computes a random integer: pop x, push random integer in [0, x). The
+    /// bound must be a positive integer.
     Rand,
+    /// Seeds the random number generator, so that the numbers it gives can be
+    /// reproduced: pop x.
+    Seed,
     /// Quit the calculator.
     Quit,
 }
 
+/// The words that name each operator.
+const WORDS: &[(&str, Op)] = &[
+    ("+", Op::Add), ("-", Op::Sub), ("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem),
+    ("pow", Op::Pow), ("abs", Op::Abs), ("min", Op::Min), ("max", Op::Max), ("sqrt", Op::Sqrt),
+    ("sin", Op::Sin), ("cos", Op::Cos), ("tan", Op::Tan), ("asin", Op::Asin), ("acos", Op::Acos),
+    ("atan", Op::Atan), ("exp", Op::Exp), ("ln", Op::Ln), ("log", Op::Log), ("cplx", Op::Cplx),
+    ("re", Op::Re), ("im", Op::Im), ("conj", Op::Conj), ("arg", Op::Arg), ("polar", Op::Polar),
+    ("rect", Op::Rect), ("floor", Op::Floor), ("ceil", Op::Ceil), ("round", Op::Round),
+    ("trunc", Op::Trunc), ("int", Op::ToInt), ("float", Op::ToFloat), ("numer", Op::Numer),
+    ("denom", Op::Denom), ("concat", Op::Concat), ("len", Op::Len), ("substr", Op::Substr),
+    ("upper", Op::Upper), ("lower", Op::Lower), ("str", Op::ToStr), ("num", Op::ToNum),
+    ("range", Op::Range), ("nth", Op::Nth), ("append", Op::Append), ("~", Op::Neg),
+    ("<->", Op::Swap), ("dup", Op::Dup), ("drop", Op::Drop), ("over", Op::Over), ("rot", Op::Rot),
+    ("-rot", Op::RotBack), ("nip", Op::Nip), ("tuck", Op::Tuck), ("pick", Op::Pick), ("roll", Op::Roll),
+    ("clear", Op::Clear), ("depth", Op::Depth), ("=", Op::Eq), ("!=", Op::Ne), ("<", Op::Lt), ("<=", Op::Le),
+    (">", Op::Gt), (">=", Op::Ge), ("and", Op::And), ("or", Op::Or), ("xor", Op::Xor), ("not", Op::Not),
+    ("band", Op::BAnd), ("bor", Op::BOr), ("bxor", Op::BXor), ("bnot", Op::BNot), ("shl", Op::Shl),
+    ("shr", Op::Shr), ("#", Op::Rand), ("seed", Op::Seed), ("quit", Op::Quit),
+];
+
+impl Op {
+    /// Looks up the operator named by a word, such as `+` or `sqrt`.
+    pub fn from_word(s: &str) -> Option<Op> {
+        return WORDS.iter().find(|(word, _)| *word == s).map(|(_, op)| *op);
+    }
+
+    /// The word that names the operator.
+    pub fn word(self) -> &'static str {
+        return WORDS.iter().find(|(_, op)| *op == self).map(|(word, _)| *word).unwrap();
+    }
+
+    /// Every operator, in the order their words are listed.
+    pub fn all() -> impl Iterator<Item = Op> {
+        return WORDS.iter().map(|(_, op)| *op);
+    }
+}
+
+/// How many steps `undo` can go back by default.
+pub const UNDO_DEPTH: usize = 100;
+
+/// The longest list that `range` will make before giving up.
+const MAX_RANGE: i64 = 1 << 20;
+
 // Stack
-// Simply a wrapper around a vector.
+// Simply a wrapper around a vector, which also keeps a history of changes so
+// that they can be undone. Only the part of the stack that a change touched is
+// saved: the mark is the lowest the stack has been since the last checkpoint,
+// everything below it is untouched, and the journal holds the values that were
+// popped from above the mark's old position, most recent last.
 pub struct Stack {
     stack: Vec<Elt>,
+    mark: usize,
+    journal: Vec<Elt>,
+    undo: VecDeque<Change>,
+    redo: Vec<Change>,
+    undo_depth: usize,
+    rng: Box<dyn RngCore>,
+}
+
+// Change
+// The values above `depth` before and after a change to the stack.
+struct Change {
+    depth: usize,
+    before: Vec<Elt>,
+    after: Vec<Elt>,
 }
 
 // Result
@@ -54,7 +624,20 @@ pub type Result<T> = result::Result<T, Error>;
 impl Stack {
     /// Creates a new Stack
     pub fn new() -> Stack {
-        return Stack { stack: vec![] };
+        return Stack::with_rng(StdRng::from_entropy());
+    }
+
+    /// Creates a new Stack which takes its random numbers from `rng`.
+    pub fn with_rng<R: RngCore + 'static>(rng: R) -> Stack {
+        return Stack {
+            stack: vec![],
+            mark: 0,
+            journal: vec![],
+            undo: VecDeque::new(),
+            redo: vec![],
+            undo_depth: UNDO_DEPTH,
+            rng: Box::new(rng),
+        };
     }
 
     /// Pushes a value onto the stack.
@@ -63,28 +646,266 @@ impl Stack {
         return Ok(());
     }
 
+    /// Counts the values on the stack.
+    pub fn len(&self) -> usize {
+        return self.stack.len();
+    }
+
+    /// Checks whether the stack has no values on it.
+    pub fn is_empty(&self) -> bool {
+        return self.stack.is_empty();
+    }
+
     /// Tries to pop a value off of the stack.
     pub fn pop(&mut self) -> Result<Elt> {
-        return match self.stack.pop() {
-            Some(e) => Ok(e),
-            None => Err(Error::Underflow),
+        let e = match self.stack.pop() {
+            Some(e) => e,
+            None => return Err(ErrorKind::Underflow.into()),
+        };
+        // Values from before the last checkpoint are saved, so that the
+        // change can be undone.
+        if self.stack.len() < self.mark {
+            self.mark = self.stack.len();
+            self.journal.push(e.clone());
+        }
+        return Ok(e);
+    }
+
+    /// Looks at the top value without popping it.
+    pub fn peek(&self) -> Result<&Elt> {
+        return self.stack.last().ok_or_else(|| ErrorKind::Underflow.into());
+    }
+
+    /// The values on the stack, from the bottom up.
+    pub fn values(&self) -> &[Elt] {
+        return &self.stack;
+    }
+
+    /// Sets how many changes `undo` can go back, forgetting any older ones.
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_depth = depth;
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Records the changes since the last checkpoint as one step, which can
+    /// be undone. Steps that change nothing are not recorded.
+    pub fn checkpoint(&mut self) {
+        let mut before = std::mem::take(&mut self.journal);
+        before.reverse();
+        let change = Change { depth: self.mark, before, after: self.stack[self.mark..].to_vec() };
+        self.mark = self.stack.len();
+
+        if change.before.is_empty() && change.after.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.record(change);
+    }
+
+    /// Discards the changes since the last checkpoint, putting back the values
+    /// that were popped, so that a line which fails part way leaves no trace.
+    pub fn rollback(&mut self) {
+        self.stack.truncate(self.mark);
+        while let Some(e) = self.journal.pop() {
+            self.stack.push(e);
+        }
+        self.mark = self.stack.len();
+    }
+
+    /// Adds a step to the undo history, forgetting the oldest if it is full.
+    fn record(&mut self, change: Change) {
+        if self.undo_depth == 0 {
+            return;
+        }
+        if self.undo.len() == self.undo_depth {
+            self.undo.pop_front();
         }
+        self.undo.push_back(change);
+    }
+
+    /// Restores the stack to how it was before the last recorded step, first
+    /// recording any changes since it. Returns false if there is nothing to
+    /// undo.
+    pub fn undo(&mut self) -> bool {
+        self.checkpoint();
+        return match self.undo.pop_back() {
+            Some(mut change) => {
+                change.after = self.stack.split_off(change.depth);
+                self.stack.append(&mut change.before);
+                self.mark = self.stack.len();
+                self.redo.push(change);
+                true
+            }
+            None => false,
+        };
+    }
+
+    /// Reapplies the last undone step. Returns false if there is nothing to
+    /// redo, including when the stack has changed since it was undone.
+    pub fn redo(&mut self) -> bool {
+        self.checkpoint();
+        return match self.redo.pop() {
+            Some(mut change) => {
+                change.before = self.stack.split_off(change.depth);
+                self.stack.append(&mut change.after);
+                self.mark = self.stack.len();
+                self.record(change);
+                true
+            }
+            None => false,
+        };
     }
 
     // Private helper functions
 
-    fn eval_add(&mut self) -> Result<()> {
-        let one: i32;
-        match self.pop()? {
-            Elt::Bool(_) => return Err(Error::Type),
-            Elt::Int(i) => one = i,
+    fn pop_num(&mut self) -> Result<Elt> {
+        return match self.pop()? {
+            e @ Elt::Int(_) | e @ Elt::Big(_) | e @ Elt::Rational(_) | e @ Elt::Float(_) => Ok(e),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    /// Pops x, then y, and pushes f(y, x) after promoting them to a common
+    /// representation.
+    fn eval_arith(&mut self, f: fn(Pair) -> Result<Elt>) -> Result<()> {
+        let x = self.pop()?;
+        let y = self.pop()?;
+        return self.push(f(num::promote(&y, &x)?)?);
+    }
+
+    /// Pops x and pushes f(x), promoting exact numbers to floats, or c(x) if x
+    /// is complex.
+    fn eval_float_fn(&mut self, f: fn(f64) -> f64, c: fn(Complex64) -> Complex64) -> Result<()> {
+        return match self.pop()? {
+            Elt::Complex(z) => self.push(Elt::Complex(c(z))),
+            x => match num::to_float(&x) {
+                Some(x) => self.push(Elt::Float(f(x))),
+                None => Err(ErrorKind::Type.into()),
+            },
+        };
+    }
+
+    fn pop_real(&mut self) -> Result<f64> {
+        return num::to_float(&self.pop_num()?).ok_or_else(|| ErrorKind::Type.into());
+    }
+
+    fn pop_complex(&mut self) -> Result<Complex64> {
+        return num::to_complex(&self.pop()?).ok_or_else(|| ErrorKind::Type.into());
+    }
+
+    fn eval_cplx(&mut self) -> Result<()> {
+        let x = self.pop_real()?;
+        let y = self.pop_real()?;
+        return self.push(Elt::Complex(Complex64::new(y, x)));
+    }
+
+    fn eval_polar(&mut self) -> Result<()> {
+        let (r, t) = self.pop_complex()?.to_polar();
+        self.push(Elt::Float(r))?;
+        return self.push(Elt::Float(t));
+    }
+
+    fn eval_rect(&mut self) -> Result<()> {
+        let t = self.pop_real()?;
+        let r = self.pop_real()?;
+        return self.push(Elt::Complex(Complex64::from_polar(r, t)));
+    }
+
+    /// Pops x and pushes it rounded to an integer, using f for floats and r
+    /// for rationals.
+    fn eval_round_fn(&mut self, f: fn(f64) -> f64, r: fn(&BigRational) -> BigRational) -> Result<()> {
+        let x = self.pop()?;
+        return self.push(num::round(x, f, r)?);
+    }
+
+    fn eval_min(&mut self) -> Result<()> {
+        let x = self.pop_num()?;
+        let y = self.pop_num()?;
+        return self.push(y.min(x));
+    }
+
+    fn eval_max(&mut self) -> Result<()> {
+        let x = self.pop_num()?;
+        let y = self.pop_num()?;
+        return self.push(y.max(x));
+    }
+
+    fn pop_str(&mut self) -> Result<String> {
+        return match self.pop()? {
+            Elt::Str(s) => Ok(s),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    fn pop_int(&mut self) -> Result<i32> {
+        return match self.pop()? {
+            Elt::Int(i) => Ok(i),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    fn eval_concat(&mut self) -> Result<()> {
+        let x = self.pop_str()?;
+        let y = self.pop_str()?;
+        return self.push(Elt::Str(y + &x));
+    }
+
+    fn pop_list(&mut self) -> Result<Vec<Elt>> {
+        return match self.pop()? {
+            Elt::List(list) => Ok(list),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    fn eval_len(&mut self) -> Result<()> {
+        let n = match self.pop()? {
+            Elt::Str(x) => x.chars().count(),
+            Elt::List(x) => x.len(),
+            _ => return Err(ErrorKind::Type.into()),
+        };
+        return self.push(Elt::Int(n as i32));
+    }
+
+    fn eval_range(&mut self) -> Result<()> {
+        let x = self.pop_int()?;
+        let y = self.pop_int()?;
+        if x as i64 - y as i64 > MAX_RANGE {
+            return Err(ErrorKind::Overflow.into());
         }
-        let two: i32;
-        match self.pop()? {
-            Elt::Bool(_) => return Err(Error::Type),
-            Elt::Int(i) => two = i,
+        return self.push(Elt::List((y..x).map(Elt::Int).collect()));
+    }
+
+    fn eval_nth(&mut self) -> Result<()> {
+        let i = self.pop_int()?;
+        let mut x = self.pop_list()?;
+        if i < 0 || i as usize >= x.len() {
+            return Err(ErrorKind::Index.into());
         }
-        return self.push(Elt::Int(one + two));
+        return self.push(x.swap_remove(i as usize));
+    }
+
+    fn eval_append(&mut self) -> Result<()> {
+        let e = self.pop()?;
+        let mut x = self.pop_list()?;
+        x.push(e);
+        return self.push(Elt::List(x));
+    }
+
+    fn eval_substr(&mut self) -> Result<()> {
+        let n = self.pop_int()?.max(0) as usize;
+        let i = self.pop_int()?.max(0) as usize;
+        let x = self.pop_str()?;
+        return self.push(Elt::Str(x.chars().skip(i).take(n).collect()));
+    }
+
+    fn eval_to_num(&mut self) -> Result<()> {
+        let x = self.pop_str()?;
+        return match Elt::parse_number(x.trim()) {
+            Some(n) => self.push(n),
+            None => Err(ErrorKind::Syntax.into()),
+        };
     }
 
     fn eval_eq(&mut self) -> Result<()> {
@@ -93,13 +914,125 @@ impl Stack {
         return self.push(Elt::Bool(one == two));
     }
 
+    fn eval_ne(&mut self) -> Result<()> {
+        let one = self.pop()?;
+        let two = self.pop()?;
+        return self.push(Elt::Bool(one != two));
+    }
+
+    /// Pops x, then y, and pushes whether f holds of how y compares to x. Real
+    /// numbers can be compared with each other, and strings with each other,
+    /// but nothing else, since complex numbers have no natural order.
+    fn eval_compare(&mut self, f: fn(Ordering) -> bool) -> Result<()> {
+        let x = self.pop()?;
+        let y = self.pop()?;
+        let order = match (&y, &x) {
+            (Elt::Str(y), Elt::Str(x)) => Some(y.cmp(x)),
+            (Elt::Complex(_), _) | (_, Elt::Complex(_)) => None,
+            (y, x) => num::cmp(y, x),
+        };
+        return match order {
+            Some(order) => self.push(Elt::Bool(f(order))),
+            None => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    fn pop_bool(&mut self) -> Result<bool> {
+        return match self.pop()? {
+            Elt::Bool(b) => Ok(b),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    /// Pops x, then y, and pushes f(y, x), for booleans.
+    fn eval_logic(&mut self, f: fn(bool, bool) -> bool) -> Result<()> {
+        let x = self.pop_bool()?;
+        let y = self.pop_bool()?;
+        return self.push(Elt::Bool(f(y, x)));
+    }
+
+    /// Pops an integer of any size.
+    fn pop_integer(&mut self) -> Result<BigInt> {
+        return match self.pop()? {
+            Elt::Int(i) => Ok(BigInt::from(i)),
+            Elt::Big(b) => Ok(b),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    /// Pops x, then y, and pushes f(y, x), for integers.
+    fn eval_bitwise(&mut self, f: fn(BigInt, BigInt) -> BigInt) -> Result<()> {
+        let x = self.pop_integer()?;
+        let y = self.pop_integer()?;
+        return self.push(num::int(f(y, x)));
+    }
+
+    /// Pops a number of bits n, then an integer x, and pushes f(x, n). The
+    /// number of bits may not be negative.
+    fn eval_shift(&mut self, f: fn(BigInt, u32) -> Result<Elt>) -> Result<()> {
+        let n = self.pop_int()?;
+        let x = self.pop_integer()?;
+        if n < 0 {
+            return Err(ErrorKind::Domain.into());
+        }
+        return self.push(f(x, n as u32)?);
+    }
+
     fn eval_neg(&mut self) -> Result<()> {
         return match self.pop()? {
             Elt::Bool(b) => self.push(Elt::Bool(!b)),
-            Elt::Int(i) => self.push(Elt::Int(-i)),
+            x => self.push(num::neg(x)?),
         };
     }
 
+    /// Checks that the stack holds at least n values, so that words which
+    /// rearrange it either succeed or leave it alone.
+    fn require(&self, n: usize) -> Result<()> {
+        return if self.len() < n { Err(ErrorKind::Underflow.into()) } else { Ok(()) };
+    }
+
+    /// Pops a count of values to reach past, which may not be negative.
+    fn pop_index(&mut self) -> Result<usize> {
+        let n = self.pop_int()?;
+        return if n < 0 { Err(ErrorKind::Index.into()) } else { Ok(n as usize) };
+    }
+
+    /// Pushes a copy of the nth value, counting from 0 at the top.
+    fn eval_pick(&mut self, n: usize) -> Result<()> {
+        self.require(n + 1)?;
+        let e = self.stack[self.len() - 1 - n].clone();
+        return self.push(e);
+    }
+
+    /// Moves the nth value, counting from 0 at the top, to the top.
+    fn eval_roll(&mut self, n: usize) -> Result<()> {
+        self.require(n + 1)?;
+        let mut above = vec![];
+        for _ in 0..n {
+            above.push(self.pop()?);
+        }
+        let e = self.pop()?;
+        while let Some(x) = above.pop() {
+            self.push(x)?;
+        }
+        return self.push(e);
+    }
+
+    fn eval_rot_back(&mut self) -> Result<()> {
+        self.require(3)?;
+        let x = self.pop()?;
+        let y = self.pop()?;
+        let z = self.pop()?;
+        self.push(x)?;
+        self.push(z)?;
+        return self.push(y);
+    }
+
+    fn eval_drop(&mut self) -> Result<()> {
+        self.pop()?;
+        return Ok(());
+    }
+
     fn eval_swap(&mut self) -> Result<()> {
         let one = self.pop()?;
         let two = self.pop()?;
@@ -109,20 +1042,174 @@ impl Stack {
 
     fn eval_rand(&mut self) -> Result<()> {
         return match self.pop()? {
-            Elt::Bool(_) => Err(Error::Type),
-            Elt::Int(i) => self.push(Elt::Int(rand::thread_rng().gen_range(0..=i)))
+            Elt::Int(i) if i > 0 => {
+                let r = self.rng.gen_range(0..i);
+                self.push(Elt::Int(r))
+            }
+            Elt::Big(b) if b.is_positive() => {
+                let r = self.rng.gen_bigint_range(&BigInt::from(0), &b);
+                self.push(num::int(r))
+            }
+            Elt::Int(_) | Elt::Big(_) => Err(ErrorKind::Domain.into()),
+            _ => Err(ErrorKind::Type.into()),
         }
     }
 
-    /// Tries to evaluate an operator using values on the stack.
+    /// Replaces the random number generator with one seeded from x, so that
+    /// the same seed always gives the same numbers.
+    fn eval_seed(&mut self) -> Result<()> {
+        let seed = self.pop_int()?;
+        self.rng = Box::new(StdRng::seed_from_u64(seed as u64));
+        return Ok(());
+    }
+
+    /// Tries to evaluate an operator using values on the stack. Errors record
+    /// the operator as the operation being attempted.
     pub fn eval(&mut self, op: Op) -> Result<()> {
+        return self.eval_op(op).map_err(|e| e.during(op.word()));
+    }
+
+    fn eval_op(&mut self, op: Op) -> Result<()> {
         return match op {
-            Op::Add => self.eval_add(),
+            Op::Add => self.eval_arith(num::add),
+            Op::Sub => self.eval_arith(num::sub),
+            Op::Mul => self.eval_arith(num::mul),
+            Op::Div => self.eval_arith(num::div),
+            Op::Rem => self.eval_arith(num::rem),
+            Op::Pow => self.eval_arith(num::pow),
+            Op::Abs => {
+                let x = self.pop()?;
+                self.push(num::abs(x)?)
+            }
+            Op::Min => self.eval_min(),
+            Op::Max => self.eval_max(),
+            Op::Sqrt => {
+                let x = self.pop()?;
+                self.push(num::sqrt(x)?)
+            }
+            Op::Sin => self.eval_float_fn(f64::sin, Complex64::sin),
+            Op::Cos => self.eval_float_fn(f64::cos, Complex64::cos),
+            Op::Tan => self.eval_float_fn(f64::tan, Complex64::tan),
+            Op::Asin => self.eval_float_fn(f64::asin, Complex64::asin),
+            Op::Acos => self.eval_float_fn(f64::acos, Complex64::acos),
+            Op::Atan => self.eval_float_fn(f64::atan, Complex64::atan),
+            Op::Exp => self.eval_float_fn(f64::exp, Complex64::exp),
+            Op::Ln => self.eval_float_fn(f64::ln, Complex64::ln),
+            Op::Log => self.eval_float_fn(f64::log10, Complex64::log10),
+            Op::Floor => self.eval_round_fn(f64::floor, BigRational::floor),
+            Op::Ceil => self.eval_round_fn(f64::ceil, BigRational::ceil),
+            Op::Round => self.eval_round_fn(f64::round, BigRational::round),
+            Op::Trunc => self.eval_round_fn(f64::trunc, BigRational::trunc),
+            Op::Numer => {
+                let x = self.pop()?;
+                self.push(num::numer(x)?)
+            }
+            Op::Denom => {
+                let x = self.pop()?;
+                self.push(num::denom(x)?)
+            }
+            Op::ToInt => {
+                let x = self.pop()?;
+                self.push(num::to_int(x)?)
+            }
+            Op::ToFloat => {
+                let x = self.pop_real()?;
+                self.push(Elt::Float(x))
+            }
+            Op::Cplx => self.eval_cplx(),
+            Op::Re => {
+                let x = self.pop()?;
+                self.push(num::re(x)?)
+            }
+            Op::Im => {
+                let x = self.pop()?;
+                self.push(num::im(x)?)
+            }
+            Op::Conj => {
+                let x = self.pop()?;
+                self.push(num::conj(x)?)
+            }
+            Op::Arg => {
+                let x = self.pop_complex()?;
+                self.push(Elt::Float(x.arg()))
+            }
+            Op::Polar => self.eval_polar(),
+            Op::Rect => self.eval_rect(),
+            Op::Concat => self.eval_concat(),
+            Op::Len => self.eval_len(),
+            Op::Substr => self.eval_substr(),
+            Op::Upper => {
+                let x = self.pop_str()?;
+                self.push(Elt::Str(x.to_uppercase()))
+            }
+            Op::Lower => {
+                let x = self.pop_str()?;
+                self.push(Elt::Str(x.to_lowercase()))
+            }
+            Op::ToStr => {
+                let x = self.pop()?;
+                self.push(Elt::Str(x.to_string()))
+            }
+            Op::ToNum => self.eval_to_num(),
+            Op::Range => self.eval_range(),
+            Op::Nth => self.eval_nth(),
+            Op::Append => self.eval_append(),
             Op::Eq => self.eval_eq(),
+            Op::Ne => self.eval_ne(),
+            Op::Lt => self.eval_compare(Ordering::is_lt),
+            Op::Le => self.eval_compare(Ordering::is_le),
+            Op::Gt => self.eval_compare(Ordering::is_gt),
+            Op::Ge => self.eval_compare(Ordering::is_ge),
+            Op::And => self.eval_logic(|y, x| y && x),
+            Op::Or => self.eval_logic(|y, x| y || x),
+            Op::Xor => self.eval_logic(|y, x| y != x),
+            Op::Not => {
+                let x = self.pop_bool()?;
+                self.push(Elt::Bool(!x))
+            }
+            Op::BAnd => self.eval_bitwise(|y, x| y & x),
+            Op::BOr => self.eval_bitwise(|y, x| y | x),
+            Op::BXor => self.eval_bitwise(|y, x| y ^ x),
+            Op::BNot => {
+                let x = self.pop_integer()?;
+                self.push(num::int(!x))
+            }
+            Op::Shl => self.eval_shift(num::shl),
+            Op::Shr => self.eval_shift(num::shr),
             Op::Neg => self.eval_neg(),
             Op::Swap => self.eval_swap(),
+            Op::Dup => self.eval_pick(0),
+            Op::Drop => self.eval_drop(),
+            Op::Over => self.eval_pick(1),
+            Op::Rot => self.eval_roll(2),
+            Op::RotBack => self.eval_rot_back(),
+            Op::Nip => {
+                self.require(2)?;
+                let x = self.pop()?;
+                self.pop()?;
+                self.push(x)
+            }
+            Op::Tuck => {
+                self.require(2)?;
+                self.eval_swap()?;
+                self.eval_pick(1)
+            }
+            Op::Pick => {
+                let n = self.pop_index()?;
+                self.eval_pick(n)
+            }
+            Op::Roll => {
+                let n = self.pop_index()?;
+                self.eval_roll(n)
+            }
+            Op::Clear => {
+                while self.pop().is_ok() {}
+                Ok(())
+            }
+            Op::Depth => self.push(Elt::Int(self.len() as i32)),
             Op::Rand => self.eval_rand(),
-            Op::Quit => Err(Error::Quit),
+            Op::Seed => self.eval_seed(),
+            Op::Quit => Err(ErrorKind::Quit.into()),
         };
     }
 }
@@ -130,6 +1217,8 @@ impl Stack {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexer::Token;
+    use crate::parser::compile;
 
     #[test]
     fn test_pop_empty1() {
@@ -137,7 +1226,7 @@ mod tests {
 
         let res = s.pop();
         assert!(res.is_err());
-        if let Err(Error::Underflow) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
@@ -150,7 +1239,7 @@ mod tests {
 
         let res = s.pop();
         assert!(res.is_err());
-        if let Err(Error::Underflow) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
@@ -171,7 +1260,7 @@ mod tests {
 
         let res = s.eval(Op::Add);
         assert!(res.is_err());
-        if let Err(Error::Type) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
@@ -182,7 +1271,7 @@ mod tests {
 
         let res = s.eval(Op::Add);
         assert!(res.is_err());
-        if let Err(Error::Type) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
@@ -203,7 +1292,7 @@ mod tests {
 
         let res = s.eval(Op::Add);
         assert!(res.is_err());
-        if let Err(Error::Type) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
@@ -234,7 +1323,7 @@ mod tests {
 
         let res = s.pop();
         assert!(res.is_err());
-        if let Err(Error::Underflow) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
@@ -244,7 +1333,7 @@ mod tests {
 
         let res = s.eval(Op::Swap);
         assert!(res.is_err());
-        if let Err(Error::Underflow) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
@@ -267,7 +1356,7 @@ mod tests {
 
         let res = s.eval(Op::Rand);
         assert!(res.is_err());
-        if let Err(Error::Type) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
     }
 
     #[test]
@@ -276,6 +1365,1016 @@ mod tests {
 
         let res = s.eval(Op::Quit);
         assert!(res.is_err());
-        if let Err(Error::Quit) = res {} else { assert!(false); }
+        if let Err(Error { kind: ErrorKind::Quit, .. }) = res {} else { assert!(false); }
+    }
+
+    fn big(i: i64) -> Elt {
+        return Elt::Big(BigInt::from(i));
+    }
+
+    fn ratio(n: i64, d: i64) -> Elt {
+        return Elt::Rational(BigRational::new(n.into(), d.into()));
+    }
+
+    /// Pushes y and x, evaluates op, and returns the result.
+    fn eval_ints(y: i32, x: i32, op: Op) -> Result<Elt> {
+        let mut s = Stack::new();
+        s.push(Elt::Int(y)).unwrap();
+        s.push(Elt::Int(x)).unwrap();
+        s.eval(op)?;
+        return s.pop();
+    }
+
+    #[test]
+    fn test_eval_add_overflow() {
+        assert_eq!(eval_ints(i32::MAX, 0, Op::Add).unwrap(), Elt::Int(i32::MAX));
+        assert_eq!(eval_ints(i32::MIN, 0, Op::Add).unwrap(), Elt::Int(i32::MIN));
+        assert_eq!(eval_ints(i32::MAX, 1, Op::Add).unwrap(), big(i32::MAX as i64 + 1));
+        assert_eq!(eval_ints(i32::MIN, -1, Op::Add).unwrap(), big(i32::MIN as i64 - 1));
+        assert_eq!(eval_ints(i32::MAX, i32::MAX, Op::Add).unwrap(), big(2 * i32::MAX as i64));
+    }
+
+    #[test]
+    fn test_eval_sub() {
+        assert_eq!(eval_ints(5, 3, Op::Sub).unwrap(), Elt::Int(2));
+        assert_eq!(eval_ints(-1, i32::MAX, Op::Sub).unwrap(), Elt::Int(i32::MIN));
+        assert_eq!(eval_ints(i32::MIN, 1, Op::Sub).unwrap(), big(i32::MIN as i64 - 1));
+        assert_eq!(eval_ints(i32::MAX, -1, Op::Sub).unwrap(), big(i32::MAX as i64 + 1));
+        assert_eq!(eval_ints(0, i32::MIN, Op::Sub).unwrap(), big(-(i32::MIN as i64)));
+    }
+
+    #[test]
+    fn test_eval_mul() {
+        assert_eq!(eval_ints(6, -7, Op::Mul).unwrap(), Elt::Int(-42));
+        assert_eq!(eval_ints(i32::MIN, 1, Op::Mul).unwrap(), Elt::Int(i32::MIN));
+        assert_eq!(eval_ints(i32::MAX, -1, Op::Mul).unwrap(), Elt::Int(i32::MIN + 1));
+        assert_eq!(eval_ints(i32::MIN, -1, Op::Mul).unwrap(), big(-(i32::MIN as i64)));
+        assert_eq!(eval_ints(i32::MAX, 2, Op::Mul).unwrap(), big(2 * i32::MAX as i64));
+        assert_eq!(eval_ints(i32::MIN, i32::MIN, Op::Mul).unwrap(), big(1 << 62));
+    }
+
+    #[test]
+    fn test_eval_div() {
+        assert_eq!(eval_ints(6, 2, Op::Div).unwrap(), Elt::Int(3));
+        assert_eq!(eval_ints(7, 2, Op::Div).unwrap(), ratio(7, 2));
+        assert_eq!(eval_ints(-7, 2, Op::Div).unwrap(), ratio(-7, 2));
+        assert_eq!(eval_ints(7, -14, Op::Div).unwrap(), ratio(-1, 2));
+        assert_eq!(eval_ints(i32::MAX, -1, Op::Div).unwrap(), Elt::Int(-i32::MAX));
+        assert_eq!(eval_ints(i32::MIN, i32::MAX, Op::Div).unwrap(), ratio(i32::MIN as i64, i32::MAX as i64));
+        assert_eq!(eval_ints(i32::MIN, 1, Op::Div).unwrap(), Elt::Int(i32::MIN));
+        assert_eq!(eval_ints(i32::MIN, -1, Op::Div).unwrap(), big(-(i32::MIN as i64)));
+        if let Err(Error { kind: ErrorKind::DivideByZero, .. }) = eval_ints(1, 0, Op::Div) {} else { assert!(false); }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_eval_rem() {
+        assert_eq!(eval_ints(7, 2, Op::Rem).unwrap(), Elt::Int(1));
+        assert_eq!(eval_ints(-7, 2, Op::Rem).unwrap(), Elt::Int(-1));
+        assert_eq!(eval_ints(i32::MIN, -1, Op::Rem).unwrap(), Elt::Int(0));
+        assert_eq!(eval_ints(i32::MAX, i32::MIN, Op::Rem).unwrap(), Elt::Int(i32::MAX));
+        if let Err(Error { kind: ErrorKind::DivideByZero, .. }) = eval_ints(1, 0, Op::Rem) {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_pow() {
+        assert_eq!(eval_ints(2, 10, Op::Pow).unwrap(), Elt::Int(1024));
+        assert_eq!(eval_ints(0, 0, Op::Pow).unwrap(), Elt::Int(1));
+        assert_eq!(eval_ints(2, 30, Op::Pow).unwrap(), Elt::Int(1 << 30));
+        assert_eq!(eval_ints(-2, 31, Op::Pow).unwrap(), Elt::Int(i32::MIN));
+        assert_eq!(eval_ints(2, 31, Op::Pow).unwrap(), big(1 << 31));
+        assert_eq!(eval_ints(-2, 32, Op::Pow).unwrap(), big(1 << 32));
+        assert_eq!(eval_ints(-2, 33, Op::Pow).unwrap(), big(-(1 << 33)));
+        assert_eq!(eval_ints(2, -1, Op::Pow).unwrap(), ratio(1, 2));
+        assert_eq!(eval_ints(-2, -3, Op::Pow).unwrap(), ratio(-1, 8));
+        assert_eq!(eval_ints(-1, -3, Op::Pow).unwrap(), Elt::Int(-1));
+        assert_eq!(eval_ints(-1, i32::MIN, Op::Pow).unwrap(), Elt::Int(1));
+        if let Err(Error { kind: ErrorKind::DivideByZero, .. }) = eval_ints(0, -1, Op::Pow) {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_abs() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(-i32::MAX)).unwrap();
+        assert!(s.eval(Op::Abs).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(i32::MAX));
+
+        s.push(Elt::Int(i32::MIN)).unwrap();
+        assert!(s.eval(Op::Abs).is_ok());
+        assert_eq!(s.pop().unwrap(), big(-(i32::MIN as i64)));
+    }
+
+    #[test]
+    fn test_eval_neg_overflow() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(i32::MIN)).unwrap();
+        assert!(s.eval(Op::Neg).is_ok());
+        assert_eq!(s.pop().unwrap(), big(-(i32::MIN as i64)));
+
+        // Negating back down into range gives an Int again.
+        s.push(big(-(i32::MIN as i64))).unwrap();
+        assert!(s.eval(Op::Neg).is_ok());
+        if let Elt::Int(i32::MIN) = s.pop().unwrap() {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_min_max() {
+        assert_eq!(eval_ints(i32::MIN, i32::MAX, Op::Min).unwrap(), Elt::Int(i32::MIN));
+        assert_eq!(eval_ints(i32::MIN, i32::MAX, Op::Max).unwrap(), Elt::Int(i32::MAX));
+        assert_eq!(eval_ints(3, -3, Op::Min).unwrap(), Elt::Int(-3));
+    }
+
+    #[test]
+    fn test_eval_arith_type() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Bool(true)).unwrap();
+        let res = s.eval(Op::Mul);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_float_promotion() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Float(0.5)).unwrap();
+        assert!(s.eval(Op::Add).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(1.5));
+
+        s.push(Elt::Float(7.0)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+        assert!(s.eval(Op::Div).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(3.5));
+
+        s.push(Elt::Int(7)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+        assert!(s.eval(Op::Div).is_ok());
+        assert_eq!(s.pop().unwrap(), ratio(7, 2));
+
+        s.push(ratio(1, 3)).unwrap();
+        s.push(Elt::Float(0.5)).unwrap();
+        assert!(s.eval(Op::Add).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(1.0 / 3.0 + 0.5));
+
+        s.push(Elt::Float(1.0)).unwrap();
+        s.push(Elt::Int(0)).unwrap();
+        assert!(s.eval(Op::Div).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_eval_float_fns() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(2)).unwrap();
+        assert!(s.eval(Op::Sqrt).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(2f64.sqrt()));
+
+        s.push(Elt::Int(3)).unwrap();
+        assert!(s.eval(Op::Floor).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(3));
+
+        s.push(Elt::Float(-2.5)).unwrap();
+        assert!(s.eval(Op::Round).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(-3.0));
+
+        s.push(Elt::Float(-2.5)).unwrap();
+        assert!(s.eval(Op::Abs).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(2.5));
+
+        s.push(Elt::Bool(true)).unwrap();
+        let res = s.eval(Op::Sin);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_to_int() {
+        let mut s = Stack::new();
+        s.push(Elt::Float(-2.9)).unwrap();
+        assert!(s.eval(Op::ToInt).is_ok());
+        if let Elt::Int(-2) = s.pop().unwrap() {} else { assert!(false); }
+
+        s.push(Elt::Float(2147483648.5)).unwrap();
+        assert!(s.eval(Op::ToInt).is_ok());
+        assert_eq!(s.pop().unwrap(), big(2147483648));
+
+        for f in &[f64::NAN, f64::INFINITY] {
+            s.push(Elt::Float(*f)).unwrap();
+            let res = s.eval(Op::ToInt);
+            assert!(res.is_err());
+            if let Err(Error { kind: ErrorKind::Overflow, .. }) = res {} else { assert!(false); }
+        }
+    }
+
+    #[test]
+    fn test_float_order() {
+        assert_eq!(Elt::Int(1), Elt::Float(1.0));
+        assert_eq!(Elt::Float(-0.0), Elt::Float(0.0));
+        assert_eq!(Elt::Float(f64::NAN), Elt::Float(f64::NAN));
+        assert!(Elt::Float(f64::NAN) > Elt::Float(f64::INFINITY));
+        assert!(Elt::Float(f64::NEG_INFINITY) < Elt::Int(i32::MIN));
+        assert!(Elt::Int(2) > Elt::Float(1.5));
+        assert!(Elt::Float(1e10) < Elt::Bool(false));
+
+        let mut elts = [Elt::Float(2.5), Elt::Bool(true), Elt::Int(-1), Elt::Float(f64::NAN), Elt::Int(3)];
+        elts.sort();
+        assert!(matches!(elts[0], Elt::Int(-1)));
+        assert_eq!(elts[1], Elt::Float(2.5));
+        assert_eq!(elts[2], Elt::Int(3));
+        assert!(matches!(elts[3], Elt::Float(f) if f.is_nan()));
+        assert_eq!(elts[4], Elt::Bool(true));
+    }
+
+    #[test]
+    fn test_eval_eq_promotion() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(2)).unwrap();
+        s.push(Elt::Float(2.0)).unwrap();
+        assert!(s.eval(Op::Eq).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Bool(true));
+    }
+
+    #[test]
+    fn test_eval_compare() {
+        let compare = |y: Elt, x: Elt, op: Op| {
+            let mut s = Stack::new();
+            s.push(y).unwrap();
+            s.push(x).unwrap();
+            return s.eval(op).and_then(|_| s.pop());
+        };
+        assert_eq!(compare(Elt::Int(1), Elt::Int(2), Op::Lt).unwrap(), Elt::Bool(true));
+        assert_eq!(compare(Elt::Int(2), Elt::Int(2), Op::Lt).unwrap(), Elt::Bool(false));
+        assert_eq!(compare(Elt::Int(2), Elt::Int(2), Op::Le).unwrap(), Elt::Bool(true));
+        assert_eq!(compare(Elt::Float(2.5), Elt::Int(2), Op::Gt).unwrap(), Elt::Bool(true));
+        assert_eq!(compare(big(1 << 40), Elt::Float(1e12), Op::Ge).unwrap(), Elt::Bool(true));
+        assert_eq!(compare(ratio(1, 3), Elt::Float(0.5), Op::Ge).unwrap(), Elt::Bool(false));
+        assert_eq!(compare(Elt::Str("abc".to_string()), Elt::Str("abd".to_string()), Op::Lt).unwrap(), Elt::Bool(true));
+        assert_eq!(compare(Elt::Int(1), Elt::Float(1.0), Op::Ne).unwrap(), Elt::Bool(false));
+        assert_eq!(compare(Elt::Int(1), Elt::Bool(true), Op::Ne).unwrap(), Elt::Bool(true));
+
+        let res = compare(Elt::Int(1), Elt::Str("1".to_string()), Op::Lt);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        let res = compare(Elt::Complex(Complex64::new(0.0, 1.0)), Elt::Int(1), Op::Gt);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        let res = compare(Elt::Bool(false), Elt::Bool(true), Op::Lt);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_parse_radix() {
+        assert_eq!(Elt::parse_number("0x1F"), Some(Elt::Int(31)));
+        assert_eq!(Elt::parse_number("0xff"), Some(Elt::Int(255)));
+        assert_eq!(Elt::parse_number("0b1010"), Some(Elt::Int(10)));
+        assert_eq!(Elt::parse_number("0o17"), Some(Elt::Int(15)));
+        assert_eq!(Elt::parse_number("-0x80000000"), Some(Elt::Int(i32::MIN)));
+        assert_eq!(Elt::parse_number("0x80000000"), Some(big(1 << 31)));
+        for s in ["0x", "0b102", "0o8", "0x-1", "0x+1", "0xG", "0b1i", "0x1/2", "-0x", "x1F"] {
+            assert_eq!(Elt::parse_number(s), None);
+        }
+    }
+
+    #[test]
+    fn test_radix_format() {
+        assert_eq!(Radix::Hex.format(&Elt::Int(31)), "0x1F");
+        assert_eq!(Radix::Hex.format(&Elt::Int(i32::MIN)), "-0x80000000");
+        assert_eq!(Radix::Bin.format(&Elt::Int(-10)), "-0b1010");
+        assert_eq!(Radix::Oct.format(&big(1 << 40)), "0o20000000000000");
+        assert_eq!(Radix::Dec.format(&Elt::Int(31)), "31");
+        assert_eq!(Radix::Hex.format(&Elt::Float(31.0)), "31.0");
+        let list = Elt::List(vec![Elt::Int(10), Elt::Str("a".to_string()), Elt::List(vec![Elt::Int(255)])]);
+        assert_eq!(Radix::Hex.format(&list), "{ 0xA \"a\" { 0xFF } }");
+        for e in [Elt::Int(-255), big(-(1 << 40))] {
+            for radix in [Radix::Bin, Radix::Oct, Radix::Hex] {
+                assert_eq!(Elt::parse_number(&radix.format(&e)), Some(e.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_bitwise() {
+        let bitwise = |y: Elt, x: Elt, op: Op| {
+            let mut s = Stack::new();
+            s.push(y).unwrap();
+            s.push(x).unwrap();
+            return s.eval(op).and_then(|_| s.pop());
+        };
+        assert_eq!(bitwise(Elt::Int(0b1100), Elt::Int(0b1010), Op::BAnd).unwrap(), Elt::Int(0b1000));
+        assert_eq!(bitwise(Elt::Int(0b1100), Elt::Int(0b1010), Op::BOr).unwrap(), Elt::Int(0b1110));
+        assert_eq!(bitwise(Elt::Int(0b1100), Elt::Int(0b1010), Op::BXor).unwrap(), Elt::Int(0b0110));
+        assert_eq!(bitwise(Elt::Int(-1), big(1 << 40), Op::BAnd).unwrap(), big(1 << 40));
+        assert_eq!(bitwise(big(1 << 40), big(1 << 40), Op::BXor).unwrap(), Elt::Int(0));
+        assert_eq!(bitwise(Elt::Int(1), Elt::Int(40), Op::Shl).unwrap(), big(1 << 40));
+        assert_eq!(bitwise(Elt::Int(-7), Elt::Int(1), Op::Shr).unwrap(), Elt::Int(-4));
+
+        let res = bitwise(Elt::Int(1), Elt::Int(-1), Op::Shl);
+        if let Err(Error { kind: ErrorKind::Domain, .. }) = res {} else { assert!(false); }
+        let res = bitwise(Elt::Float(1.0), Elt::Int(1), Op::BAnd);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        let res = bitwise(Elt::Int(1), big(1 << 40), Op::Shl);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+
+        let mut s = Stack::new();
+        s.push(Elt::Int(0)).unwrap();
+        assert!(s.eval(Op::BNot).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(-1));
+        s.push(Elt::Int(i32::MIN)).unwrap();
+        assert!(s.eval(Op::BNot).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(i32::MAX));
+    }
+
+    #[test]
+    fn test_eval_logic() {
+        let logic = |y: bool, x: bool, op: Op| {
+            let mut s = Stack::new();
+            s.push(Elt::Bool(y)).unwrap();
+            s.push(Elt::Bool(x)).unwrap();
+            s.eval(op).unwrap();
+            return s.pop().unwrap();
+        };
+        assert_eq!(logic(true, false, Op::And), Elt::Bool(false));
+        assert_eq!(logic(true, true, Op::And), Elt::Bool(true));
+        assert_eq!(logic(false, true, Op::Or), Elt::Bool(true));
+        assert_eq!(logic(false, false, Op::Or), Elt::Bool(false));
+        assert_eq!(logic(true, true, Op::Xor), Elt::Bool(false));
+        assert_eq!(logic(true, false, Op::Xor), Elt::Bool(true));
+
+        let mut s = Stack::new();
+        s.push(Elt::Bool(true)).unwrap();
+        assert!(s.eval(Op::Not).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Bool(false));
+        s.push(Elt::Int(0)).unwrap();
+        let res = s.eval(Op::Not);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Bool(true)).unwrap();
+        let res = s.eval(Op::And);
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    fn push_str(s: &mut Stack, x: &str) {
+        s.push(Elt::Str(x.to_string())).unwrap();
+    }
+
+    #[test]
+    fn test_eval_concat() {
+        let mut s = Stack::new();
+        push_str(&mut s, "foo");
+        push_str(&mut s, "bar");
+        assert!(s.eval(Op::Concat).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Str("foobar".to_string()));
+
+        push_str(&mut s, "foo");
+        s.push(Elt::Int(1)).unwrap();
+        let res = s.eval(Op::Concat);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_len() {
+        let mut s = Stack::new();
+        push_str(&mut s, "héllo");
+        assert!(s.eval(Op::Len).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(5));
+    }
+
+    #[test]
+    fn test_eval_substr() {
+        let mut s = Stack::new();
+        push_str(&mut s, "hello world");
+        s.push(Elt::Int(6)).unwrap();
+        s.push(Elt::Int(3)).unwrap();
+        assert!(s.eval(Op::Substr).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Str("wor".to_string()));
+
+        push_str(&mut s, "hello");
+        s.push(Elt::Int(-2)).unwrap();
+        s.push(Elt::Int(100)).unwrap();
+        assert!(s.eval(Op::Substr).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Str("hello".to_string()));
+    }
+
+    #[test]
+    fn test_eval_upper_lower() {
+        let mut s = Stack::new();
+        push_str(&mut s, "MiXeD");
+        assert!(s.eval(Op::Upper).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Str("MIXED".to_string()));
+        push_str(&mut s, "MiXeD");
+        assert!(s.eval(Op::Lower).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Str("mixed".to_string()));
+    }
+
+    #[test]
+    fn test_eval_to_str() {
+        let mut s = Stack::new();
+        for (e, x) in [(Elt::Int(-3), "-3"), (Elt::Float(2.0), "2.0"), (Elt::Bool(true), "true"),
+                           (Elt::Str("s".to_string()), "s"), (Elt::Sym("x".to_string()), "'x")] {
+            s.push(e).unwrap();
+            assert!(s.eval(Op::ToStr).is_ok());
+            assert_eq!(s.pop().unwrap(), Elt::Str(x.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_eval_to_num() {
+        let mut s = Stack::new();
+        push_str(&mut s, " 42 ");
+        assert!(s.eval(Op::ToNum).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(42));
+        push_str(&mut s, "1e3");
+        assert!(s.eval(Op::ToNum).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(1000.0));
+
+        push_str(&mut s, "forty-two");
+        let res = s.eval(Op::ToNum);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_range() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(-1)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+        assert!(s.eval(Op::Range).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::List(vec![Elt::Int(-1), Elt::Int(0), Elt::Int(1)]));
+
+        s.push(Elt::Int(2)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+        assert!(s.eval(Op::Range).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::List(vec![]));
+
+        s.push(Elt::Int(0)).unwrap();
+        s.push(Elt::Int(i32::MAX)).unwrap();
+        let res = s.eval(Op::Range);
+        if let Err(Error { kind: ErrorKind::Overflow, .. }) = res {} else { assert!(false); }
+        s.push(Elt::Int(i32::MIN)).unwrap();
+        s.push(Elt::Int(i32::MAX)).unwrap();
+        let res = s.eval(Op::Range);
+        if let Err(Error { kind: ErrorKind::Overflow, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_nth() {
+        let mut s = Stack::new();
+        s.push(Elt::List(vec![Elt::Int(1), Elt::Bool(true)])).unwrap();
+        s.push(Elt::Int(1)).unwrap();
+        assert!(s.eval(Op::Nth).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Bool(true));
+
+        for i in &[-1, 2] {
+            s.push(Elt::List(vec![Elt::Int(1), Elt::Bool(true)])).unwrap();
+            s.push(Elt::Int(*i)).unwrap();
+            let res = s.eval(Op::Nth);
+            assert!(res.is_err());
+            if let Err(Error { kind: ErrorKind::Index, .. }) = res {} else { assert!(false); }
+        }
+    }
+
+    #[test]
+    fn test_eval_append_len() {
+        let mut s = Stack::new();
+        s.push(Elt::List(vec![])).unwrap();
+        s.push(Elt::Str("a".to_string())).unwrap();
+        assert!(s.eval(Op::Append).is_ok());
+        assert!(s.eval(Op::Len).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(1));
+
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+        let res = s.eval(Op::Append);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_list_display() {
+        let list = Elt::List(vec![Elt::Int(1), Elt::Str("a b".to_string()), Elt::List(vec![])]);
+        assert_eq!(list.to_string(), r#"{ 1 "a b" { } }"#);
+        let tokens = vec![Token::Word("1".to_string()), Token::Word("+".to_string())];
+        let quote = Elt::Quote(Rc::new(compile(tokens, vec![]).unwrap()));
+        assert_eq!(quote.to_string(), "[ 1 + ]");
+    }
+
+    #[test]
+    fn test_eval_big_arith() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(2)).unwrap();
+        s.push(Elt::Int(100)).unwrap();
+        assert!(s.eval(Op::Pow).is_ok());
+        s.push(Elt::Int(1)).unwrap();
+        assert!(s.eval(Op::Add).is_ok());
+        assert_eq!(s.pop().unwrap().to_string(), "1267650600228229401496703205377");
+
+        // Shrinking back into range gives an Int again.
+        s.push(big(1 << 40)).unwrap();
+        s.push(big(1 << 39)).unwrap();
+        assert!(s.eval(Op::Div).is_ok());
+        if let Elt::Int(2) = s.pop().unwrap() {} else { assert!(false); }
+
+        s.push(big(1 << 40)).unwrap();
+        s.push(Elt::Int(0)).unwrap();
+        let res = s.eval(Op::Rem);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::DivideByZero, .. }) = res {} else { assert!(false); }
+
+        s.push(big(1 << 40)).unwrap();
+        s.push(Elt::Float(0.5)).unwrap();
+        assert!(s.eval(Op::Mul).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float((1u64 << 39) as f64));
+    }
+
+    #[test]
+    fn test_big_order() {
+        assert!(big(1 << 40) > Elt::Int(i32::MAX));
+        assert!(big(-(1 << 40)) < Elt::Int(i32::MIN));
+        assert!(big(1 << 40) < big(1 << 41));
+        assert_eq!(big(1 << 40), Elt::Float((1u64 << 40) as f64));
+        assert!(big(1 << 40) < Elt::Float(f64::NAN));
+        assert!(big(1 << 40) < Elt::Bool(false));
+    }
+
+    #[test]
+    fn test_eval_rand_big() {
+        let mut s = Stack::new();
+        s.push(big(1 << 40)).unwrap();
+        assert!(s.eval(Op::Rand).is_ok());
+        let r = s.pop().unwrap();
+        assert!(r >= Elt::Int(0));
+        assert!(r < big(1 << 40));
+    }
+
+    #[test]
+    fn test_eval_rand_bounds() {
+        // A bound of 1 can only give 0.
+        let mut s = Stack::new();
+        for _ in 0..10 {
+            s.push(Elt::Int(1)).unwrap();
+            assert!(s.eval(Op::Rand).is_ok());
+            assert_eq!(s.pop().unwrap(), Elt::Int(0));
+        }
+
+        for x in [Elt::Int(0), Elt::Int(-5), Elt::Int(i32::MIN), big(-(1 << 40))] {
+            s.push(x).unwrap();
+            let res = s.eval(Op::Rand);
+            assert!(res.is_err());
+            if let Err(Error { kind: ErrorKind::Domain, .. }) = res {} else { assert!(false); }
+        }
+
+        s.push(Elt::Float(2.0)).unwrap();
+        let res = s.eval(Op::Rand);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    fn rolls(s: &mut Stack, n: usize) -> Vec<Elt> {
+        let mut rolls = vec![];
+        for _ in 0..n {
+            s.push(Elt::Int(1000)).unwrap();
+            s.eval(Op::Rand).unwrap();
+            rolls.push(s.pop().unwrap());
+        }
+        return rolls;
+    }
+
+    #[test]
+    fn test_eval_seed() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(42)).unwrap();
+        assert!(s.eval(Op::Seed).is_ok());
+        let first = rolls(&mut s, 5);
+
+        s.push(Elt::Int(42)).unwrap();
+        assert!(s.eval(Op::Seed).is_ok());
+        assert_eq!(rolls(&mut s, 5), first);
+
+        s.push(Elt::Int(-42)).unwrap();
+        assert!(s.eval(Op::Seed).is_ok());
+        assert_ne!(rolls(&mut s, 5), first);
+    }
+
+    #[test]
+    fn test_with_rng() {
+        let mut s = Stack::with_rng(StdRng::seed_from_u64(7));
+        let mut t = Stack::with_rng(StdRng::seed_from_u64(7));
+        assert_eq!(rolls(&mut s, 5), rolls(&mut t, 5));
+
+        // Any generator will do.
+        let mut s = Stack::with_rng(rand::rngs::mock::StepRng::new(0, 0));
+        assert_eq!(rolls(&mut s, 3), vec![Elt::Int(0); 3]);
+    }
+
+    #[test]
+    fn test_parse_number_big() {
+        assert_eq!(Elt::parse_number("-12345678901234567890").unwrap().to_string(), "-12345678901234567890");
+        if let Some(Elt::Big(_)) = Elt::parse_number("2147483648") {} else { assert!(false); }
+        if let Some(Elt::Int(i32::MIN)) = Elt::parse_number("-2147483648") {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_rational_arith() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Int(3)).unwrap();
+        assert!(s.eval(Op::Div).is_ok());
+        s.push(ratio(1, 6)).unwrap();
+        assert!(s.eval(Op::Add).is_ok());
+        assert_eq!(s.pop().unwrap(), ratio(1, 2));
+
+        // Adding up to a whole number gives an integer again.
+        s.push(ratio(1, 3)).unwrap();
+        s.push(ratio(2, 3)).unwrap();
+        assert!(s.eval(Op::Add).is_ok());
+        if let Elt::Int(1) = s.pop().unwrap() {} else { assert!(false); }
+
+        s.push(ratio(-7, 2)).unwrap();
+        assert!(s.eval(Op::Abs).is_ok());
+        assert!(s.eval(Op::Neg).is_ok());
+        assert_eq!(s.pop().unwrap(), ratio(-7, 2));
+    }
+
+    #[test]
+    fn test_eval_rational_conversions() {
+        let mut s = Stack::new();
+        for (op, x) in [(Op::Floor, -4), (Op::Ceil, -3), (Op::Round, -4), (Op::Trunc, -3), (Op::ToInt, -3)] {
+            s.push(ratio(-7, 2)).unwrap();
+            assert!(s.eval(op).is_ok());
+            if let Elt::Int(i) = s.pop().unwrap() { assert_eq!(i, x); } else { assert!(false); }
+        }
+
+        s.push(ratio(-6, 4)).unwrap();
+        assert!(s.eval(Op::Numer).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(-3));
+        s.push(ratio(-6, 4)).unwrap();
+        assert!(s.eval(Op::Denom).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(2));
+        s.push(Elt::Int(5)).unwrap();
+        assert!(s.eval(Op::Denom).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(1));
+
+        s.push(ratio(1, 4)).unwrap();
+        assert!(s.eval(Op::ToFloat).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(0.25));
+
+        s.push(Elt::Float(0.5)).unwrap();
+        let res = s.eval(Op::Numer);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_rational_order_display() {
+        assert!(ratio(1, 3) < Elt::Float(0.34));
+        assert!(ratio(1, 3) > Elt::Float(1.0 / 3.0));
+        assert!(ratio(-1, 2) < Elt::Int(0));
+        assert_eq!(ratio(4, 2), Elt::Int(2));
+        assert_eq!(ratio(1, 3).to_string(), "1/3");
+        assert_eq!(ratio(2, -6).to_string(), "-1/3");
+    }
+
+    #[test]
+    fn test_parse_number_rational() {
+        assert_eq!(Elt::parse_number("-2/6").unwrap(), ratio(-1, 3));
+        if let Some(Elt::Int(3)) = Elt::parse_number("6/2") {} else { assert!(false); }
+        for s in &["1/0", "1/-2", "1/", "/2", "1/2/3", "1.5/2"] {
+            assert!(Elt::parse_number(s).is_none());
+        }
+    }
+
+    fn cplx(re: f64, im: f64) -> Elt {
+        return Elt::Complex(Complex64::new(re, im));
+    }
+
+    #[test]
+    fn test_eval_complex_arith() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+        assert!(s.eval(Op::Cplx).is_ok());
+        s.push(cplx(0.0, 1.0)).unwrap();
+        assert!(s.eval(Op::Mul).is_ok());
+        assert_eq!(s.pop().unwrap(), cplx(-2.0, 1.0));
+
+        s.push(cplx(0.0, 1.0)).unwrap();
+        s.push(cplx(0.0, 1.0)).unwrap();
+        assert!(s.eval(Op::Mul).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(-1));
+
+        s.push(cplx(3.0, 4.0)).unwrap();
+        s.push(ratio(1, 2)).unwrap();
+        assert!(s.eval(Op::Div).is_ok());
+        assert_eq!(s.pop().unwrap(), cplx(6.0, 8.0));
+
+        s.push(cplx(3.0, 4.0)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+        let res = s.eval(Op::Rem);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+
+        s.push(cplx(1.0, 2.0)).unwrap();
+        s.push(Elt::Int(3)).unwrap();
+        let res = s.eval(Op::Max);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Type, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_complex_parts() {
+        let mut s = Stack::new();
+        for (op, x) in [(Op::Re, Elt::Float(3.0)), (Op::Im, Elt::Float(-4.0)), (Op::Conj, cplx(3.0, 4.0)),
+                        (Op::Abs, Elt::Float(5.0)), (Op::Neg, cplx(-3.0, 4.0))] {
+            s.push(cplx(3.0, -4.0)).unwrap();
+            assert!(s.eval(op).is_ok());
+            assert_eq!(s.pop().unwrap(), x);
+        }
+
+        s.push(ratio(1, 2)).unwrap();
+        assert!(s.eval(Op::Im).is_ok());
+        if let Elt::Int(0) = s.pop().unwrap() {} else { assert!(false); }
+
+        s.push(Elt::Int(-1)).unwrap();
+        assert!(s.eval(Op::Arg).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(std::f64::consts::PI));
+
+        s.push(cplx(3.0, 4.0)).unwrap();
+        assert!(s.eval(Op::ToFloat).is_err());
+
+        s.push(Elt::Str("1".to_string())).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+        assert!(s.eval(Op::Cplx).is_err());
+    }
+
+    #[test]
+    fn test_eval_complex_polar() {
+        let mut s = Stack::new();
+        s.push(cplx(0.0, 2.0)).unwrap();
+        assert!(s.eval(Op::Polar).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(std::f64::consts::FRAC_PI_2));
+        assert_eq!(s.pop().unwrap(), Elt::Float(2.0));
+
+        s.push(Elt::Int(2)).unwrap();
+        s.push(Elt::Int(0)).unwrap();
+        assert!(s.eval(Op::Rect).is_ok());
+        assert_eq!(s.pop().unwrap(), cplx(2.0, 0.0));
+    }
+
+    #[test]
+    fn test_eval_complex_fns() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(-4)).unwrap();
+        assert!(s.eval(Op::Sqrt).is_ok());
+        assert_eq!(s.pop().unwrap(), cplx(0.0, 2.0));
+
+        s.push(cplx(-1.0, 0.0)).unwrap();
+        assert!(s.eval(Op::Ln).is_ok());
+        assert_eq!(s.pop().unwrap(), cplx(0.0, std::f64::consts::PI));
+
+        // Real numbers stay real, even out of their domain.
+        s.push(Elt::Int(-1)).unwrap();
+        assert!(s.eval(Op::Ln).is_ok());
+        if let Elt::Float(f) = s.pop().unwrap() { assert!(f.is_nan()); } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_parse_number_complex() {
+        assert_eq!(Elt::parse_number("2i").unwrap(), cplx(0.0, 2.0));
+        assert_eq!(Elt::parse_number("-1/2i").unwrap(), cplx(0.0, -0.5));
+        assert_eq!(Elt::parse_number("1-2i").unwrap(), cplx(1.0, -2.0));
+        assert_eq!(Elt::parse_number("1.5e-3+2e2i").unwrap(), cplx(0.0015, 200.0));
+        assert_eq!(Elt::parse_number("-1e+2-1i").unwrap(), cplx(-100.0, -1.0));
+        for s in &["i", "-i", "1+i", "2ii", "1+2+3i", "x+2i"] {
+            assert!(Elt::parse_number(s).is_none());
+        }
+    }
+
+    #[test]
+    fn test_complex_display() {
+        assert_eq!(cplx(1.0, 2.0).to_string(), "1.0+2.0i");
+        assert_eq!(cplx(0.0, -0.5).to_string(), "0.0-0.5i");
+        let z = cplx(-1.5, 1e-7);
+        assert_eq!(Elt::parse_number(&z.to_string()).unwrap(), z);
+    }
+
+    #[test]
+    fn test_eval_error_op() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        let e = s.eval(Op::Add).unwrap_err();
+        if let ErrorKind::Underflow = e.kind {} else { assert!(false); }
+        assert_eq!(e.op(), Some("+"));
+        assert_eq!(e.token(), None);
+        assert_eq!(e.to_string(), "stack underflow in `+`");
+
+        // A more specific op or token is never replaced.
+        let e = e.during("f").at("f", 3..4).at("g", 5..6);
+        assert_eq!((e.op(), e.token(), e.span()), (Some("+"), Some("f"), Some(3..4)));
+    }
+
+    #[test]
+    fn test_op_words() {
+        assert_eq!(Op::from_word("sqrt"), Some(Op::Sqrt));
+        assert_eq!(Op::from_word("nope"), None);
+        for (word, op) in WORDS {
+            assert_eq!(Op::from_word(word), Some(*op));
+            assert_eq!(op.word(), *word);
+        }
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::error::Error as _;
+        let e = Error::from(ErrorKind::IO(io::Error::other("disk")));
+        assert_eq!(e.to_string(), "IO error: disk");
+        assert!(e.source().is_some());
+        assert!(Error::from(ErrorKind::Type).source().is_none());
+    }
+
+    fn push_ints(s: &mut Stack, ints: &[i32]) {
+        for i in ints {
+            s.push(Elt::Int(*i)).unwrap();
+        }
+    }
+
+    fn contents(s: &Stack) -> Vec<Elt> {
+        return s.stack.clone();
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut s = Stack::new();
+        push_ints(&mut s, &[1, 2, 3]);
+        s.checkpoint();
+        s.eval(Op::Add).unwrap();
+        s.eval(Op::Mul).unwrap();
+        push_ints(&mut s, &[4]);
+        s.checkpoint();
+        assert_eq!(contents(&s), vec![Elt::Int(5), Elt::Int(4)]);
+
+        assert!(s.undo());
+        assert_eq!(contents(&s), vec![Elt::Int(1), Elt::Int(2), Elt::Int(3)]);
+        assert!(s.undo());
+        assert!(s.is_empty());
+        assert!(!s.undo());
+
+        assert!(s.redo());
+        assert!(s.redo());
+        assert_eq!(contents(&s), vec![Elt::Int(5), Elt::Int(4)]);
+        assert!(!s.redo());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut s = Stack::new();
+        push_ints(&mut s, &[1, 2, 3]);
+        s.checkpoint();
+        push_ints(&mut s, &[4]);
+        s.eval(Op::Add).unwrap();
+        s.eval(Op::Mul).unwrap();
+        s.push(Elt::Str("a".to_string())).unwrap();
+        assert!(s.eval(Op::Add).is_err());
+        s.rollback();
+        assert_eq!(contents(&s), vec![Elt::Int(1), Elt::Int(2), Elt::Int(3)]);
+
+        // Nothing was recorded, so there is only the first step to undo.
+        assert!(s.undo());
+        assert!(s.is_empty());
+        assert!(!s.undo());
+    }
+
+    #[test]
+    fn test_undo_journal() {
+        // Values pushed and popped within a step are not saved.
+        let mut s = Stack::new();
+        push_ints(&mut s, &[1, 2]);
+        s.checkpoint();
+        push_ints(&mut s, &[10, 20]);
+        s.eval(Op::Add).unwrap();
+        s.pop().unwrap();
+        s.pop().unwrap();
+        assert!(s.journal.len() == 1 && s.mark == 1);
+        s.checkpoint();
+        assert_eq!(s.undo.back().unwrap().before, vec![Elt::Int(2)]);
+        assert!(s.undo.back().unwrap().after.is_empty());
+
+        assert!(s.undo());
+        assert_eq!(contents(&s), vec![Elt::Int(1), Elt::Int(2)]);
+    }
+
+    #[test]
+    fn test_undo_pending() {
+        // Undoing with changes since the last checkpoint undoes those first,
+        // and leaves nothing to redo once the stack changes again.
+        let mut s = Stack::new();
+        push_ints(&mut s, &[1]);
+        s.checkpoint();
+        s.pop().unwrap();
+        push_ints(&mut s, &[7]);
+        assert!(s.undo());
+        assert_eq!(contents(&s), vec![Elt::Int(1)]);
+        push_ints(&mut s, &[2]);
+        assert!(!s.redo());
+        assert_eq!(contents(&s), vec![Elt::Int(1), Elt::Int(2)]);
+    }
+
+    #[test]
+    fn test_undo_depth() {
+        let mut s = Stack::new();
+        s.set_undo_depth(2);
+        for i in 0..5 {
+            push_ints(&mut s, &[i]);
+            s.checkpoint();
+        }
+        // Steps that change nothing are not recorded.
+        s.checkpoint();
+        assert!(s.undo() && s.undo());
+        assert!(!s.undo());
+        assert_eq!(s.len(), 3);
+
+        s.set_undo_depth(0);
+        push_ints(&mut s, &[5]);
+        assert!(!s.undo());
+    }
+
+    #[test]
+    fn test_eval_shuffles() {
+        let ints = |v: &[i32]| v.iter().map(|i| Elt::Int(*i)).collect::<Vec<Elt>>();
+        for (op, after) in [(Op::Dup, vec![1, 2, 3, 3]), (Op::Drop, vec![1, 2]), (Op::Over, vec![1, 2, 3, 2]),
+                            (Op::Rot, vec![2, 3, 1]), (Op::RotBack, vec![3, 1, 2]), (Op::Nip, vec![1, 3]),
+                            (Op::Tuck, vec![1, 3, 2, 3]), (Op::Clear, vec![]), (Op::Depth, vec![1, 2, 3, 3])] {
+            let mut s = Stack::new();
+            push_ints(&mut s, &[1, 2, 3]);
+            assert!(s.eval(op).is_ok());
+            assert_eq!(contents(&s), ints(&after));
+        }
+    }
+
+    #[test]
+    fn test_eval_pick_roll() {
+        let mut s = Stack::new();
+        push_ints(&mut s, &[1, 2, 3, 2]);
+        assert!(s.eval(Op::Pick).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(1));
+
+        push_ints(&mut s, &[2]);
+        assert!(s.eval(Op::Roll).is_ok());
+        assert_eq!(contents(&s), vec![Elt::Int(2), Elt::Int(3), Elt::Int(1)]);
+
+        push_ints(&mut s, &[0]);
+        assert!(s.eval(Op::Roll).is_ok());
+        assert_eq!(s.len(), 3);
+
+        push_ints(&mut s, &[-1]);
+        let res = s.eval(Op::Pick);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Index, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_shuffle_underflow() {
+        // Shuffles that fail leave the stack alone.
+        for (op, n) in [(Op::Dup, 0), (Op::Drop, 0), (Op::Over, 1), (Op::Rot, 2), (Op::RotBack, 2),
+                        (Op::Nip, 1), (Op::Tuck, 1)] {
+            let mut s = Stack::new();
+            push_ints(&mut s, &vec![7; n]);
+            let res = s.eval(op);
+            assert!(res.is_err());
+            if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
+            assert_eq!(s.len(), n);
+        }
+
+        let mut s = Stack::new();
+        push_ints(&mut s, &[1, 2, 2]);
+        let res = s.eval(Op::Roll);
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
+        assert_eq!(contents(&s), vec![Elt::Int(1), Elt::Int(2)]);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut s = Stack::new();
+        let res = s.peek();
+        assert!(res.is_err());
+        if let Err(Error { kind: ErrorKind::Underflow, .. }) = res {} else { assert!(false); }
+
+        push_ints(&mut s, &[1, 2]);
+        assert_eq!(s.peek().unwrap(), &Elt::Int(2));
+        assert_eq!(s.len(), 2);
+    }
+
+    #[test]
+    fn test_undo_roll_clear() {
+        let mut s = Stack::new();
+        push_ints(&mut s, &[1, 2, 3]);
+        s.checkpoint();
+        assert!(s.eval(Op::Rot).is_ok());
+        s.checkpoint();
+        assert!(s.eval(Op::Clear).is_ok());
+        s.checkpoint();
+        assert!(s.undo());
+        assert_eq!(contents(&s), vec![Elt::Int(2), Elt::Int(3), Elt::Int(1)]);
+        assert!(s.undo());
+        assert_eq!(contents(&s), vec![Elt::Int(1), Elt::Int(2), Elt::Int(3)]);
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::result;
+
+use num_complex::Complex64;
+use num_rational::BigRational;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::env::Env;
+use crate::lexer::Token;
+use crate::num;
+use crate::parser::{compile, is_identifier, is_name};
+use crate::rpn::{Elt, Error, ErrorKind, Result, Stack};
+use crate::vm::Code;
+
+// Session
+// Everything needed to pick up a calculation where it was left: the stack,
+// from the bottom up, the variables, and the bodies of user-defined words.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub stack: Vec<Elt>,
+    pub vars: BTreeMap<String, Elt>,
+    pub words: BTreeMap<String, Vec<Token>>,
+}
+
+impl Session {
+    /// Takes a copy of the state of a stack and environment.
+    pub fn capture(stack: &Stack, env: &Env) -> Session {
+        return Session {
+            stack: stack.values().to_vec(),
+            vars: env.vars().map(|(name, val)| (name.clone(), val.clone())).collect(),
+            words: env.words().map(|(name, body)| (name.clone(), body.clone())).collect(),
+        };
+    }
+
+    /// Replaces the contents of a stack and environment with the session's.
+    /// Words are compiled again, and the stack is changed one value at a time,
+    /// so that restoring a session can be undone like any other line. Nothing
+    /// is changed if the session holds a name that could not have been
+    /// defined, or a number that could not have been made.
+    pub fn restore(self, stack: &mut Stack, env: &mut Env) -> Result<()> {
+        let mut restored = Env::new();
+        for (name, body) in self.words {
+            check_name(&name)?;
+            restored.define(&name, Rc::new(compile(body, vec![])?))?;
+        }
+        for (name, val) in self.vars {
+            check_name(&name)?;
+            restored.store(&name, normalize(val)?)?;
+        }
+        let values = self.stack.into_iter().map(normalize).collect::<Result<Vec<Elt>>>()?;
+        *env = restored;
+
+        while !stack.is_empty() {
+            stack.pop()?;
+        }
+        for e in values {
+            stack.push(e)?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the session to a file, as JSON.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| ErrorKind::IO(e.into()))?;
+        return fs::write(path, json + "\n").map_err(|e| ErrorKind::IO(e).into());
+    }
+
+    /// Reads a session from a file written by `save`.
+    pub fn load(path: &str) -> Result<Session> {
+        let json = fs::read_to_string(path).map_err(ErrorKind::IO)?;
+        return serde_json::from_str(&json).map_err(|e| ErrorKind::IO(io::Error::from(e)).into());
+    }
+}
+
+/// Requires a saved name to be one that a variable or word could be given.
+fn check_name(name: &str) -> Result<()> {
+    if !is_name(name) {
+        return Err(Error::from(ErrorKind::Syntax).during(name));
+    }
+    return Ok(());
+}
+
+/// Puts a loaded value into the form the calculator keeps it in, since a file
+/// may hold numbers that are equal to others but written differently, such as
+/// a ratio not in lowest terms, or a small integer stored as a big one.
+fn normalize(e: Elt) -> Result<Elt> {
+    return match e {
+        Elt::Big(b) => Ok(num::int(b)),
+        // Zero denominators are already refused while loading.
+        Elt::Rational(r) => {
+            let (n, d) = r.into();
+            Ok(num::rational(BigRational::new(n, d)))
+        }
+        Elt::Sym(name) if !is_identifier(&name) => Err(Error::from(ErrorKind::Syntax).during(&name)),
+        Elt::List(items) => Ok(Elt::List(items.into_iter().map(normalize).collect::<Result<_>>()?)),
+        e => Ok(e),
+    };
+}
+
+/// Saves a quotation as its tokens.
+pub(crate) fn serialize_quote<S: Serializer>(code: &Rc<Code>, s: S) -> result::Result<S::Ok, S::Error> {
+    return code.tokens.serialize(s);
+}
+
+/// Loads a quotation saved by `serialize_quote`, compiling it again.
+pub(crate) fn deserialize_quote<'de, D: Deserializer<'de>>(d: D) -> result::Result<Rc<Code>, D::Error> {
+    let tokens = Vec::<Token>::deserialize(d)?;
+    return compile(tokens, vec![]).map(Rc::new).map_err(D::Error::custom);
+}
+
+/// The floats that JSON has no numbers for, which are saved as their names.
+const NON_FINITE: &[(&str, f64)] = &[("NaN", f64::NAN), ("inf", f64::INFINITY), ("-inf", f64::NEG_INFINITY)];
+
+/// A float as saved: a number if it is finite, or its name if not.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SavedFloat {
+    Finite(f64),
+    Named(String),
+}
+
+impl SavedFloat {
+    fn new(x: f64) -> SavedFloat {
+        return match NON_FINITE.iter().find(|(_, y)| x == *y || (x.is_nan() && y.is_nan())) {
+            Some((name, _)) => SavedFloat::Named(name.to_string()),
+            None => SavedFloat::Finite(x),
+        };
+    }
+
+    fn value<E: serde::de::Error>(self) -> result::Result<f64, E> {
+        return match self {
+            SavedFloat::Finite(x) => Ok(x),
+            SavedFloat::Named(name) => match NON_FINITE.iter().find(|(n, _)| *n == name) {
+                Some((_, x)) => Ok(*x),
+                None => Err(E::custom(format!("`{}` is not a float", name))),
+            },
+        };
+    }
+}
+
+/// Saves a float, so that NaN and the infinities load again.
+pub(crate) fn serialize_float<S: Serializer>(x: &f64, s: S) -> result::Result<S::Ok, S::Error> {
+    return SavedFloat::new(*x).serialize(s);
+}
+
+/// Loads a float saved by `serialize_float`.
+pub(crate) fn deserialize_float<'de, D: Deserializer<'de>>(d: D) -> result::Result<f64, D::Error> {
+    return SavedFloat::deserialize(d)?.value();
+}
+
+/// Saves a complex number as its real and imaginary parts, each as a float.
+pub(crate) fn serialize_complex<S: Serializer>(z: &Complex64, s: S) -> result::Result<S::Ok, S::Error> {
+    return (SavedFloat::new(z.re), SavedFloat::new(z.im)).serialize(s);
+}
+
+/// Loads a complex number saved by `serialize_complex`.
+pub(crate) fn deserialize_complex<'de, D: Deserializer<'de>>(d: D) -> result::Result<Complex64, D::Error> {
+    let (re, im) = <(SavedFloat, SavedFloat)>::deserialize(d)?;
+    return Ok(Complex64::new(re.value()?, im.value()?));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpn::Error;
+
+    #[test]
+    fn test_capture_restore() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        for e in [Elt::Int(1), Elt::Str("a".to_string()), Elt::List(vec![Elt::Bool(true), Elt::Float(0.5)])] {
+            stack.push(e).unwrap();
+        }
+        env.store("x", Elt::Sym("y".to_string())).unwrap();
+        let inc = Rc::new(compile(vec![Token::Word("1".to_string()), Token::Word("+".to_string())], vec![]).unwrap());
+        env.define("inc", inc.clone()).unwrap();
+        stack.push(Elt::Quote(inc)).unwrap();
+        let session = Session::capture(&stack, &env);
+
+        // Round trip through JSON, then restore over a different state.
+        let json = serde_json::to_string(&session).unwrap();
+        let session: Session = serde_json::from_str(&json).unwrap();
+        assert_eq!(session, Session::capture(&stack, &env));
+        let mut other = Stack::new();
+        let mut other_env = Env::new();
+        other.push(Elt::Int(9)).unwrap();
+        other_env.store("z", Elt::Int(0)).unwrap();
+        assert!(session.restore(&mut other, &mut other_env).is_ok());
+        assert_eq!(Session::capture(&other, &other_env), Session::capture(&stack, &env));
+        assert!(other_env.word("inc").is_some());
+        assert!(other_env.recall("z").is_err());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("hw04-session-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut session = Session::default();
+        session.stack.push(Elt::Int(42));
+        assert!(session.save(path).is_ok());
+        assert_eq!(Session::load(path).unwrap(), session);
+
+        fs::write(path, "{ not json").unwrap();
+        let res = Session::load(path);
+        if let Err(Error { kind: ErrorKind::IO(_), .. }) = res {} else { assert!(false); }
+        fs::remove_file(path).unwrap();
+
+        let res = Session::load(path);
+        if let Err(Error { kind: ErrorKind::IO(_), .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_restore_normalizes() {
+        let restore = |json: &str| {
+            let session: Session = serde_json::from_str(json).unwrap();
+            let (mut stack, mut env) = (Stack::new(), Env::new());
+            stack.push(Elt::Int(7)).unwrap();
+            let res = session.restore(&mut stack, &mut env);
+            return res.map(|()| (stack.values().to_vec(), env));
+        };
+        let (values, env) = restore(r#"{"stack": [{"Rational": [[1, [4]], [1, [2]]]}, {"Rational": [[1, [1]], [-1, [2]]]},
+            {"Big": [1, [5]]}, {"List": [{"Big": [-1, [0, 1]]}]}], "vars": {"x": {"Rational": [[1, [2]], [1, [6]]]}},
+            "words": {}}"#).unwrap();
+        let shown: Vec<String> = values.iter().map(Elt::to_string).collect();
+        assert_eq!(shown, ["2", "-1/2", "5", "{ -4294967296 }"]);
+        if let Elt::Big(_) = values[2] { assert!(false); }
+        assert_eq!(env.recall("x").unwrap().to_string(), "1/3");
+
+        let res: serde_json::Result<Session> = serde_json::from_str(r#"{"stack": [{"Rational": [[1, [1]], [0, []]]}],
+            "vars": {}, "words": {}}"#);
+        assert!(res.is_err());
+        let res = restore(r#"{"stack": [{"Sym": "1x"}], "vars": {}, "words": {}}"#);
+        if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+        let res = restore(r#"{"stack": [], "vars": {"dup": {"Int": 1}}, "words": {}}"#);
+        if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+        let res = restore(r#"{"stack": [], "vars": {}, "words": {"a b": []}}"#);
+        if let Err(Error { kind: ErrorKind::Syntax, .. }) = res {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let stack = vec![
+            Elt::Float(f64::NAN), Elt::Float(f64::INFINITY), Elt::Float(-0.5),
+            Elt::Complex(Complex64::new(f64::NEG_INFINITY, 1.0)),
+        ];
+        let mut session = Session { stack, ..Session::default() };
+        session.vars.insert("x".to_string(), Elt::List(vec![Elt::Float(f64::NEG_INFINITY)]));
+        let json = serde_json::to_string(&session).unwrap();
+        let loaded: Session = serde_json::from_str(&json).unwrap();
+        let shown: Vec<String> = loaded.stack.iter().map(Elt::to_string).collect();
+        assert_eq!(shown, ["NaN", "inf", "-0.5", "-inf+1.0i"]);
+        assert_eq!(loaded.vars["x"].to_string(), "{ -inf }");
+
+        let res: serde_json::Result<Session> = serde_json::from_str(r#"{"stack": [{"Float": "big"}], "vars": {}, "words": {}}"#);
+        assert!(res.is_err());
+    }
+}
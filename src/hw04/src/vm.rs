@@ -0,0 +1,438 @@
+use std::io::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::lexer::Token;
+use crate::rpn::{Elt, Error, ErrorKind, Op, Result, Stack};
+
+/// How deeply user-defined words may call each other before giving up. Calls
+/// keep their frames on the heap, so this can be generous.
+const MAX_DEPTH: usize = 100_000;
+
+/// How deeply quotations run by words such as `call` and `map` may nest before
+/// giving up. Each one runs in a native call, so this stays small.
+const MAX_NESTING: usize = 256;
+
+#[derive(Debug)]
+/// A single instruction of compiled code. Literals are parsed and built-in
+/// words looked up when the code is compiled, so that running it only has to
+/// resolve user-defined words and variables.
+pub enum Instr {
+    /// Pushes a number, boolean, string or quoted name.
+    Push(Elt),
+    /// Runs a built-in operation on the stack.
+    Op(Op),
+    /// Calls a user-defined word, or pushes a variable if there is no such word.
+    Name(String),
+    /// Fails with a syntax error, for a word that was not understood.
+    Fail,
+    Store,
+    Forget,
+    Words,
+    /// Pushes the index of a `do` loop, 1 being the innermost.
+    Index(usize),
+    Call,
+    Map,
+    Filter,
+    Fold,
+    Each,
+    /// `: name body ;`
+    Define(String, Rc<Code>),
+    /// `[ body ]`
+    Quote(Rc<Code>),
+    /// Jumps unconditionally.
+    Jump(usize),
+    /// Pops a boolean for an `if`, jumping if it is false.
+    If(usize),
+    /// Pops a boolean for a `while`, jumping if it is false.
+    While(usize),
+    /// Pops the start and limit of a `do` loop, jumping past its end if it
+    /// runs no times.
+    Do(usize),
+    /// Steps the innermost `do` loop, jumping back to its body unless it is
+    /// done.
+    Loop(usize),
+    /// Marks the start of the items of a list literal.
+    ListStart,
+    /// Collects everything pushed since the matching `ListStart` into a list.
+    ListEnd,
+}
+
+impl Instr {
+    /// Names the word that an instruction runs, for reporting errors, given the
+    /// token it was compiled from.
+    pub fn word(&self, token: &Token) -> String {
+        return match (self, token) {
+            (Instr::If(_), _) => "if".to_string(),
+            (Instr::While(_), _) => "while".to_string(),
+            (Instr::Do(_) | Instr::Loop(_), _) => "do".to_string(),
+            (Instr::ListStart | Instr::ListEnd, _) => "{".to_string(),
+            (Instr::Quote(_), _) => "[".to_string(),
+            (Instr::Define(_, _), _) => ":".to_string(),
+            (_, Token::Str(_)) => "\"".to_string(),
+            (_, Token::Word(s)) => s.to_string(),
+        };
+    }
+}
+
+// Code
+// A compiled line or word body: a flat sequence of instructions, each with the
+// position of the token it came from, along with the tokens themselves and
+// their spans, if they are known.
+#[derive(Debug, Default)]
+pub struct Code {
+    pub tokens: Vec<Token>,
+    pub spans: Vec<Range<usize>>,
+    pub instrs: Vec<(Instr, usize)>,
+}
+
+// Vm
+// Runs compiled code against a stack and environment, writing any output, such
+// as the listing from `words`, to `out`.
+pub struct Vm<'e> {
+    stack: &'e mut Stack,
+    env: &'e mut Env,
+    out: &'e mut dyn Write,
+    /// The index and limit of each enclosing `do` loop, innermost last.
+    loops: Vec<(i32, i32)>,
+    /// The stack depth at the start of each enclosing list literal.
+    lists: Vec<usize>,
+    /// How many user-defined words are currently executing, so that runaway
+    /// recursion becomes an error.
+    depth: usize,
+    /// How many of those are running in a native call of their own.
+    nesting: usize,
+}
+
+impl Vm<'_> {
+    /// Creates a virtual machine to run code on a stack and environment.
+    pub fn new<'e>(stack: &'e mut Stack, env: &'e mut Env, out: &'e mut dyn Write) -> Vm<'e> {
+        return Vm { stack, env, out, loops: vec![], lists: vec![], depth: 0, nesting: 0 };
+    }
+
+    /// Runs compiled code. Calls to user-defined words push a frame rather
+    /// than recursing, so that deep recursion only costs heap. Errors record
+    /// the word being run, and are located at its token if its span is known.
+    pub fn run(&mut self, code: Rc<Code>) -> Result<()> {
+        // The code being run and where it is up to, and the same for each of
+        // the words that called it.
+        let mut code = code;
+        let mut pc = 0;
+        let mut frames: Vec<(Rc<Code>, usize)> = vec![];
+
+        loop {
+            let (instr, _) = match code.instrs.get(pc) {
+                Some(instr) => instr,
+                None => match frames.pop() {
+                    Some((caller, at)) => {
+                        self.depth -= 1;
+                        code = caller;
+                        pc = at;
+                        continue;
+                    }
+                    None => return Ok(()),
+                },
+            };
+            pc += 1;
+
+            let mut e = match self.step(instr, &mut pc) {
+                Ok(None) => continue,
+                Ok(Some(_)) if self.depth >= MAX_DEPTH => Error::from(ErrorKind::Recursion),
+                Ok(Some(callee)) => {
+                    self.depth += 1;
+                    frames.push((code, pc));
+                    code = callee;
+                    pc = 0;
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            // Unwind the calls, letting each one locate the error in turn.
+            self.depth -= frames.len();
+            frames.push((code, pc));
+            for (code, pc) in frames.iter().rev() {
+                let (instr, pos) = &code.instrs[pc - 1];
+                let token = &code.tokens[*pos];
+                e = e.during(&instr.word(token));
+                if let Some(span) = code.spans.get(*pos) {
+                    e = e.at(&token.to_string(), span.clone());
+                }
+            }
+            return Err(e);
+        }
+    }
+
+    /// Runs a single instruction, moving `pc` if it jumps, and giving the code
+    /// to run next if it calls a word or quotation.
+    fn step(&mut self, instr: &Instr, pc: &mut usize) -> Result<Option<Rc<Code>>> {
+        match instr {
+            Instr::Push(e) => self.stack.push(e.clone())?,
+            Instr::Op(op) => self.stack.eval(*op)?,
+            Instr::Name(s) => match self.env.word(s) {
+                Some(code) => return Ok(Some(code)),
+                None => self.stack.push(self.env.recall(s)?)?,
+            },
+            Instr::Fail => return Err(ErrorKind::Syntax.into()),
+            Instr::Store => {
+                let name = self.pop_sym()?;
+                let val = self.stack.pop()?;
+                self.env.store(&name, val)?;
+            }
+            Instr::Forget => {
+                let name = self.pop_sym()?;
+                self.env.forget(&name)?;
+            }
+            Instr::Words => {
+                for (name, body) in self.env.words() {
+                    let body: Vec<String> = body.iter().map(Token::to_string).collect();
+                    writeln!(self.out, ": {} {} ;", name, body.join(" ")).map_err(ErrorKind::IO)?;
+                }
+            }
+            Instr::Index(back) => match self.loops.len().checked_sub(*back) {
+                Some(n) => self.stack.push(Elt::Int(self.loops[n].0))?,
+                None => return Err(ErrorKind::Unbalanced.into()),
+            },
+            Instr::Call => return Ok(Some(self.pop_quote()?)),
+            Instr::Map => self.eval_map()?,
+            Instr::Filter => self.eval_filter()?,
+            Instr::Fold => self.eval_fold()?,
+            Instr::Each => self.eval_each()?,
+            Instr::Define(name, code) => self.env.define(name, code.clone())?,
+            Instr::Quote(body) => self.stack.push(Elt::Quote(body.clone()))?,
+            Instr::Jump(to) => *pc = *to,
+            Instr::If(to) | Instr::While(to) => {
+                if !self.pop_bool()? {
+                    *pc = *to;
+                }
+            }
+            Instr::Do(to) => {
+                let start = self.pop_int()?;
+                let limit = self.pop_int()?;
+                if start < limit {
+                    self.loops.push((start, limit));
+                } else {
+                    *pc = *to;
+                }
+            }
+            Instr::Loop(to) => {
+                if let Some((i, limit)) = self.loops.last_mut() {
+                    *i += 1;
+                    if *i < *limit {
+                        *pc = *to;
+                    } else {
+                        self.loops.pop();
+                    }
+                }
+            }
+            Instr::ListStart => self.lists.push(self.stack.len()),
+            Instr::ListEnd => {
+                // The items may not consume anything from outside the braces.
+                let start = self.lists.pop().ok_or(ErrorKind::Unbalanced)?;
+                let n = self.stack.len().checked_sub(start).ok_or(ErrorKind::Underflow)?;
+                let mut list = vec![];
+                for _ in 0..n {
+                    list.push(self.stack.pop()?);
+                }
+                list.reverse();
+                self.stack.push(Elt::List(list))?;
+            }
+        }
+        return Ok(None);
+    }
+
+    /// Pops a quotation and a list, and pushes the list of results of running
+    /// the quotation on each item.
+    fn eval_map(&mut self) -> Result<()> {
+        let code = self.pop_quote()?;
+        let mut mapped = vec![];
+        for e in self.pop_list()? {
+            self.stack.push(e)?;
+            self.call(&code)?;
+            mapped.push(self.stack.pop()?);
+        }
+        return self.stack.push(Elt::List(mapped));
+    }
+
+    /// Pops a quotation and a list, and pushes the list of items for which
+    /// the quotation gives true.
+    fn eval_filter(&mut self) -> Result<()> {
+        let code = self.pop_quote()?;
+        let mut kept = vec![];
+        for e in self.pop_list()? {
+            self.stack.push(e.clone())?;
+            self.call(&code)?;
+            if self.pop_bool()? {
+                kept.push(e);
+            }
+        }
+        return self.stack.push(Elt::List(kept));
+    }
+
+    /// Pops a quotation, an initial value and a list, and combines the value
+    /// with each item in turn using the quotation.
+    fn eval_fold(&mut self) -> Result<()> {
+        let code = self.pop_quote()?;
+        let init = self.stack.pop()?;
+        let list = self.pop_list()?;
+        self.stack.push(init)?;
+        for e in list {
+            self.stack.push(e)?;
+            self.call(&code)?;
+        }
+        return Ok(());
+    }
+
+    /// Pops a quotation and a list, and runs the quotation on each item.
+    fn eval_each(&mut self) -> Result<()> {
+        let code = self.pop_quote()?;
+        for e in self.pop_list()? {
+            self.stack.push(e)?;
+            self.call(&code)?;
+        }
+        return Ok(());
+    }
+
+    /// Runs the compiled body of a user-defined word or quotation in a native
+    /// call, counting it towards both the recursion depth and the nesting.
+    fn call(&mut self, code: &Rc<Code>) -> Result<()> {
+        if self.depth >= MAX_DEPTH || self.nesting >= MAX_NESTING {
+            return Err(ErrorKind::Recursion.into());
+        }
+        self.depth += 1;
+        self.nesting += 1;
+        let res = self.run(code.clone());
+        self.nesting -= 1;
+        self.depth -= 1;
+        return res;
+    }
+
+    /// Pops a quotation off of the stack, giving its compiled code.
+    fn pop_quote(&mut self) -> Result<Rc<Code>> {
+        return match self.stack.pop()? {
+            Elt::Quote(code) => Ok(code),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    /// Pops a list off of the stack.
+    fn pop_list(&mut self) -> Result<Vec<Elt>> {
+        return match self.stack.pop()? {
+            Elt::List(list) => Ok(list),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    /// Pops a quoted name off of the stack.
+    fn pop_sym(&mut self) -> Result<String> {
+        return match self.stack.pop()? {
+            Elt::Sym(name) => Ok(name),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    /// Pops a boolean off of the stack.
+    fn pop_bool(&mut self) -> Result<bool> {
+        return match self.stack.pop()? {
+            Elt::Bool(b) => Ok(b),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+
+    /// Pops an integer off of the stack.
+    fn pop_int(&mut self) -> Result<i32> {
+        return match self.stack.pop()? {
+            Elt::Int(i) => Ok(i),
+            _ => Err(ErrorKind::Type.into()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::compile;
+
+    fn run(stack: &mut Stack, env: &mut Env, line: &str) -> Result<()> {
+        let (tokens, spans): (Vec<Token>, Vec<Range<usize>>) = tokenize(line)?.into_iter().unzip();
+        return Vm::new(stack, env, &mut io::sink()).run(Rc::new(compile(tokens, spans)?));
+    }
+
+    #[test]
+    fn test_compile_jumps() {
+        let (tokens, _): (Vec<Token>, Vec<Range<usize>>) = tokenize("true if 1 else 2 then 3 0 do i loop").unwrap().into_iter().unzip();
+        let code = compile(tokens, vec![]).unwrap();
+        let instrs: Vec<String> = code.instrs.iter().map(|(instr, pos)| format!("{:?}@{}", instr, pos)).collect();
+        assert_eq!(instrs, vec!["Push(Bool(true))@0", "If(4)@1", "Push(Int(1))@2", "Jump(5)@1", "Push(Int(2))@4",
+                                "Push(Int(3))@6", "Push(Int(0))@7", "Do(10)@8", "Index(1)@9", "Loop(8)@8"]);
+    }
+
+    #[test]
+    fn test_run_calls() {
+        let mut stack = Stack::new();
+        let mut env = Env::new();
+        assert!(run(&mut stack, &mut env, ": fact dup 1 = if else dup 1 - fact * then ; 10 fact [ 2 * ] call").is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(7257600));
+
+        // Quotations are compiled once, however many times they are copied or
+        // run.
+        assert!(run(&mut stack, &mut env, "{ 1 2 } [ 1 + ] map [ 2 * ] dup").is_ok());
+        match (stack.pop().unwrap(), stack.pop().unwrap()) {
+            (Elt::Quote(x), Elt::Quote(y)) => assert!(Rc::ptr_eq(&x, &y)),
+            _ => assert!(false),
+        }
+        assert_eq!(stack.pop().unwrap(), Elt::List(vec![Elt::Int(2), Elt::Int(3)]));
+
+        // Errors inside words are located at the word called on the line.
+        let e = run(&mut stack, &mut env, ": bad 1 0 / ; 1 bad").unwrap_err();
+        if let ErrorKind::DivideByZero = e.kind {} else { assert!(false); }
+        assert_eq!((e.token(), e.span()), (Some("bad"), Some(16..19)));
+        assert!(e.to_string().starts_with("division by zero in `/`"));
+
+        // Words may recurse deeply, but quotations that run themselves may not.
+        assert!(run(&mut stack, &mut env, "300 fact 300 fact /").is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(1));
+        let e = run(&mut stack, &mut env, ": again dup call ; [ again ] again").unwrap_err();
+        if let ErrorKind::Recursion = e.kind {} else { assert!(false); }
+    }
+
+    #[test]
+    fn test_compiled_loop_speedup() {
+        const N: i32 = 20000;
+        const TRIES: usize = 5;
+        let body = "1 + dup 7 % -";
+
+        // Compiled once, with the VM doing the looping. The fastest of a few
+        // tries is taken for each, so that a busy machine does not decide it.
+        let mut compiled = Duration::MAX;
+        let mut result = None;
+        for _ in 0..TRIES {
+            let (mut stack, mut env) = (Stack::new(), Env::new());
+            let start = Instant::now();
+            assert!(run(&mut stack, &mut env, &format!("0 {} 0 do {} loop", N, body)).is_ok());
+            compiled = compiled.min(start.elapsed());
+            result = stack.pop().ok();
+        }
+
+        // Tokenized and compiled again on every pass, like working from the
+        // source each time.
+        let mut interpreted = Duration::MAX;
+        for _ in 0..TRIES {
+            let (mut again, mut env) = (Stack::new(), Env::new());
+            let start = Instant::now();
+            assert!(again.push(Elt::Int(0)).is_ok());
+            for _ in 0..N {
+                assert!(run(&mut again, &mut env, body).is_ok());
+            }
+            interpreted = interpreted.min(start.elapsed());
+            assert_eq!(again.pop().ok(), result);
+        }
+
+        assert!(compiled * 2 < interpreted);
+    }
+}